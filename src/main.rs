@@ -1,27 +1,71 @@
 #![allow(dead_code)]
 #![allow(unused_variables)]
+#![allow(non_snake_case, non_camel_case_types)] //Chunk names keep the casing from the PNG spec
+#![allow(clippy::upper_case_acronyms, clippy::module_inception)]
 
-use std::collections::HashMap;
-use std::error::Error;
-mod png;
-use crate::png::png::Png;
-use crate::png::{IDHRChunk, PLTEChunk, IDATChunk, IENDChunk, tIMEChunk, bKGDChunk, gAMAChunk, cHRMChunk, dSIGChunk, eXIfChunk, hISTChunk,
-    iCCPChunk, iTXtChunk, pHYsChunk, sBITChunk, sPLTChunk, sRGBChunk, sTERChunk, tEXtChunk, tRNSChunk, zTXtChunk, Chunk};
-use std::ffi::OsStr;
-use std::fs::File;
+use std::collections::{HashMap, HashSet};
 use std::io::Read;
-use std::path::Path;
-
+use crate::png::error::PngError;
+mod png;
+use crate::png::png::{DecodeWarning, Png};
+use crate::png::options::{DecodeOptions, Strictness};
+use crate::png::chunks::is_critical_type;
+pub use crate::png::compare::{compare, ComparisonReport};
+pub use crate::png::png::PNG_SIGNATURE;
+pub use crate::png::crc::Crc32;
+pub use crate::png::inflate::Adler32;
+use crate::png::{IHDRChunk, PLTEChunk, IDATChunk, IENDChunk, tIMEChunk, bKGDChunk, gAMAChunk, cHRMChunk, dSIGChunk, eXIfChunk, hISTChunk,
+    iCCPChunk, iTXtChunk, pHYsChunk, sBITChunk, sPLTChunk, sRGBChunk, sTERChunk, tEXtChunk, tRNSChunk, zTXtChunk, acTLChunk, fcTLChunk, fdATChunk, sCALChunk, UnknownChunk, Chunk};
 
-//TODO: Implement decompression, defiltering and interlacing methods
 fn main() {
-    let mut png = Png::new(""); //Add path here
+    let png = match Png::new("") { //Add path here
+        Ok(png) => png,
+        Err(e) => {
+            println!("{}", e);
+            return;
+        }
+    };
     //println!("{:?}", png);
     let mut png_decoder = PngDecoder::new(png);
-    png_decoder.get_all_chunks();
+    if let Err(e) = png_decoder.get_all_chunks() {
+        println!("{}", e);
+    }
     println!("{:?}", png_decoder.png_file.chunk_list)
 }
 
+//Loads and parses a file with the given options, the returned Png keeps the options for the pixel decoding stages
+pub fn decode_file_with(path: &str, options: DecodeOptions) -> Result<Png<'_>, PngError> {
+    parse_with(Png::open_unverified(path)?, options)
+}
+
+//Shared by decode_file_with and Png::with_options so files and in-memory data go through the same checks
+pub(crate) fn parse_with(mut png: Png<'_>, options: DecodeOptions) -> Result<Png<'_>, PngError> {
+    if let Some(max_offset) = options.skip_leading_garbage {
+        png.skip_leading_garbage(max_offset);
+    }
+    //Not something lenient mode works around, without the signature there's no telling the data is a PNG at all
+    if !png.signature_verified() {
+        return Err(PngError::invalid_signature(png.leading_bytes()));
+    }
+    png.set_options(options);
+
+    let mut png_decoder = PngDecoder::new(png);
+    png_decoder.get_all_chunks()?;
+    Ok(png_decoder.png_file)
+}
+
+//For Png::from_reader_with_options, png only holds the signature and the chunks are pulled from reader one at a time
+pub(crate) fn parse_from_reader<R: Read>(mut png: Png<'static>, reader: R, options: DecodeOptions) -> Result<Png<'static>, PngError> {
+    png.set_options(options);
+    let mut png_decoder = PngDecoder::new(png);
+    png_decoder.get_chunks_from(reader)?;
+    Ok(png_decoder.png_file)
+}
+
+//Chunks the spec allows at most once, IDAT, sPLT and the text chunks can repeat
+const SINGLE_INSTANCE_CHUNKS: [&[u8; 4]; 17] = [b"IHDR", b"PLTE", b"IEND", b"tRNS", b"gAMA", b"cHRM", b"sRGB", b"iCCP", b"sBIT",
+    b"bKGD", b"hIST", b"pHYs", b"tIME", b"sTER", b"sCAL", b"eXIf", b"acTL"];
+
 #[derive(Debug)]
 struct PngDecoder<'a> {
     png_file: Png<'a>,
@@ -36,7 +80,7 @@ I'm going to match the ascii with a set list of ascii codes as we won't have to
 impl<'a> PngDecoder<'a> {
     fn new(png_file: Png<'a>) -> Self {
         let mut chunk_type_map = HashMap::new();
-        chunk_type_map.insert(vec![73, 72, 68, 82], "IDHR".to_string());
+        chunk_type_map.insert(vec![73, 72, 68, 82], "IHDR".to_string());
         chunk_type_map.insert(vec![73, 68, 65, 84], "IDAT".to_string());
         chunk_type_map.insert(vec![80, 76, 84, 69], "PLTE".to_string());
         chunk_type_map.insert(vec![98, 75, 71, 68], "bKGD".to_string());
@@ -57,49 +101,197 @@ impl<'a> PngDecoder<'a> {
         chunk_type_map.insert(vec![116, 82, 78, 83], "tRNS".to_string());
         chunk_type_map.insert(vec![122, 84, 88, 116], "zTXt".to_string());
         chunk_type_map.insert(vec![73, 69, 78, 68], "IEND".to_string());
+        chunk_type_map.insert(vec![97, 99, 84, 76], "acTL".to_string());
+        chunk_type_map.insert(vec![102, 99, 84, 76], "fcTL".to_string());
+        chunk_type_map.insert(vec![102, 100, 65, 84], "fdAT".to_string());
+        chunk_type_map.insert(vec![115, 67, 65, 76], "sCAL".to_string());
 
         Self {png_file, chunk_type_map}
     }
 
-    fn get_all_chunks(&mut self) -> Result<(), Box<dyn Error>> {
+    fn get_all_chunks(&mut self) -> Result<(), PngError> {
+        let options = self.png_file.options().clone();
+        let mut seen = HashSet::new();
+        while !self.read_chunk(&options, &mut seen)? {}
+
+        //Nothing may follow IEND, junk appended by a download or a tool is only let through when the options say so
+        let trailing = self.png_file.remaining_bytes();
+        if trailing > 0 {
+            if !options.allow_trailing_data {
+                return Err(PngError::TrailingData(trailing));
+            }
+            let reason = PngError::TrailingData(trailing).to_string();
+            self.png_file.add_warning(DecodeWarning { chunk_type: "IEND".to_string(), reason });
+        }
+        Ok(())
+    }
+
+    /*Same chunk loop fed from a Read source, only the chunk being parsed is buffered and each one replaces the last.
+    Stops right after IEND so whatever the source sends afterwards is left unread*/
+    fn get_chunks_from<R: Read>(&mut self, mut reader: R) -> Result<(), PngError> {
+        let options = self.png_file.options().clone();
+        let mut seen = HashSet::new();
+        let mut offset = PNG_SIGNATURE.len();
         loop {
-            let length = self.png_file.big_endian_u32()?;
-
-            let key_bytes = self.png_file.read_bytes(4)?;
-            println!("{:?}", key_bytes);
-            let chunk_type = self.chunk_type_map.get(&key_bytes)
-                .ok_or_else(|| Box::<dyn Error>::from("Unexpected chunk type: None"))?;
-
-            let chunk = match chunk_type.as_str() {
-                "IDHR" => Chunk::IDHR(IDHRChunk::new(length, &mut self.png_file)?),
-                "PLTE" => Chunk::PLTE(PLTEChunk::new(length, &mut self.png_file)?),
-                "IDAT" => Chunk::IDAT(IDATChunk::new(length, &mut self.png_file)?),
-                "tIME" => Chunk::tIME(tIMEChunk::new(length, &mut self.png_file)?),
-                "gAMA" => Chunk::gAMA(gAMAChunk::new(length, &mut self.png_file)?),
-                "cHRM" => Chunk::cHRM(cHRMChunk::new(length, &mut self.png_file)?),
-                "bKGD" => Chunk::bKGD(bKGDChunk::new(length, &mut self.png_file)?),
-                "tEXt" => Chunk::tEXt(tEXtChunk::new(length, &mut self.png_file)?),
-                "dSIG" => Chunk::dSIG(dSIGChunk::new(length, &mut self.png_file)?),
-                "eXIf" => Chunk::eXIf(eXIfChunk::new(length, &mut self.png_file)?),
-                "hIST" => Chunk::hIST(hISTChunk::new(length, &mut self.png_file)?),
-                "iCCP" => Chunk::iCCP(iCCPChunk::new(length, &mut self.png_file)?),
-                "iTXt" => Chunk::iTXt(iTXtChunk::new(length, &mut self.png_file)?),
-                "pHYs" => Chunk::pHYs(pHYsChunk::new(length, &mut self.png_file)?),
-                "sBIT" => Chunk::sBIT(sBITChunk::new(length, &mut self.png_file)?),
-                "sPLT" => Chunk::sPLT(sPLTChunk::new(length, &mut self.png_file)?),
-                "sRGB" => Chunk::sRGB(sRGBChunk::new(length, &mut self.png_file)?),
-                "sTER" => Chunk::sTER(sTERChunk::new(length, &mut self.png_file)?),
-                "tRNS" => Chunk::tRNS(tRNSChunk::new(length, &mut self.png_file)?),
-                "zTXt" => Chunk::zTXt(zTXtChunk::new(length, &mut self.png_file)?),
-                "IEND" => {
-                    let iend_chunk = Chunk::IEND(IENDChunk::new(length, &mut self.png_file)?);
-                    self.png_file.add_chunk(iend_chunk)?;
-                    break;
-                }
-                _ => return Err(Box::<dyn Error>::from(format!("Unexpected chunk type: {}", chunk_type))),
+            let mut chunk = Vec::with_capacity(8);
+            reader.by_ref().take(8).read_to_end(&mut chunk)?;
+            //A short header or body is handed over as is, read_chunk then fails on it the same way it would at the end of a file
+            if chunk.len() == 8 {
+                let length = u32::from_be_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+                options.check_chunk_size(length, &chunk[4..8])?;
+                //take() instead of resizing up front so a corrupt length can't allocate gigabytes before the data turns out to be missing
+                reader.by_ref().take(length as u64 + 4).read_to_end(&mut chunk)?;
+            }
+            let chunk_size = chunk.len();
+            self.png_file.load_chunk(chunk);
+            let was_iend = self.read_chunk(&options, &mut seen).map_err(|e| e.offset_by(offset))?;
+            offset += chunk_size;
+            if was_iend {
+                return Ok(());
+            }
+        }
+    }
+
+    //Reads, checks and stores the chunk at the current position, true once that was IEND
+    fn read_chunk(&mut self, options: &DecodeOptions, seen: &mut HashSet<[u8; 4]>) -> Result<bool, PngError> {
+        if options.is_cancelled() {
+            return Err(PngError::Cancelled);
+        }
+        options.check_chunk_count(self.png_file.chunk_list.len())?;
+
+        //Running out exactly between chunks means the file was cut short rather than a chunk being corrupt
+        if self.png_file.remaining_bytes() == 0 {
+            return Err(PngError::MissingIend);
+        }
+
+        let chunk_start = self.png_file.position();
+        let length = self.png_file.big_endian_u32()?;
+        let key_bytes = self.png_file.read_bytes(4)?;
+        //Both checked before any chunk parser allocates, the length field comes straight from the file so it can't be trusted
+        options.check_chunk_size(length, &key_bytes)?;
+        let available = self.png_file.remaining_bytes();
+        if length as usize > available {
+            let chunk_type = Some(String::from_utf8_lossy(&key_bytes).into_owned());
+            return Err(PngError::UnexpectedEof { chunk_type, at: self.png_file.position(), needed: length as usize, available });
+        }
+
+        self.png_file.begin_chunk(length);
+        let raw_type = [key_bytes[0], key_bytes[1], key_bytes[2], key_bytes[3]];
+        if self.png_file.chunk_list.is_empty() && &raw_type != b"IHDR" {
+            return Err(PngError::IhdrNotFirst(String::from_utf8_lossy(&raw_type).into_owned()));
+        }
+
+        //Unknown ancillary chunks (lowercase first letter) are safe to ignore so they're kept as raw bytes, unknown critical ones still fail
+        let ancillary = !is_critical_type(&raw_type);
+        let known = self.chunk_type_map.contains_key(&key_bytes);
+        let duplicate = SINGLE_INSTANCE_CHUNKS.contains(&&raw_type) && !seen.insert(raw_type);
+        if !ancillary && !known && options.tolerate_unknown {
+            let chunk_type = String::from_utf8_lossy(&raw_type).into_owned();
+            self.png_file.add_warning(DecodeWarning { chunk_type, reason: "unknown critical chunk kept as raw bytes".to_string() });
+        }
+        let chunk = if options.treat_as_raw.contains(&raw_type) || ((ancillary || options.tolerate_unknown) && !known) {
+            Chunk::Unknown(UnknownChunk::new(length, raw_type, &mut self.png_file).map_err(|e| e.in_chunk(&raw_type))?)
+        } else {
+            let order = if options.strict_ordering { self.check_chunk_order(&raw_type) } else { Ok(()) };
+            //Duplicates and misplaced chunks go through the same handling as parse errors so lenient mode only keeps an ancillary chunk's bytes
+            let parsed = if duplicate {
+                Err(PngError::DuplicateChunk(String::from_utf8_lossy(&raw_type).into_owned()))
+            } else if let Err(e) = order {
+                Err(e)
+            } else {
+                self.parse_chunk(length, &key_bytes)
+                    .and_then(|chunk| self.png_file.check_chunk_consumed(chunk_start, length, &raw_type).map(|()| chunk))
+                    .map_err(|e| e.in_chunk(&raw_type))
             };
-            self.png_file.add_chunk(chunk)?;
+            match parsed {
+                Ok(chunk) => chunk,
+                //A broken ancillary chunk isn't worth losing the image over, keep its bytes and carry on from its CRC
+                Err(e) if ancillary && options.strictness == Strictness::Lenient => {
+                    let chunk_type = String::from_utf8_lossy(&raw_type).into_owned();
+                    self.png_file.add_warning(DecodeWarning { chunk_type, reason: e.to_string() });
+                    self.png_file.seek(chunk_start + 8)?;
+                    Chunk::Unknown(UnknownChunk::new(length, raw_type, &mut self.png_file).map_err(|e| e.in_chunk(&raw_type))?)
+                }
+                Err(e) => return Err(e),
+            }
+        };
+        if !options.ignore_crc {
+            self.png_file.check_crc(chunk_start, length)?;
+        }
+        //check_crc works from the file bytes so this just puts the stream right after the CRC whatever the parser did
+        self.png_file.seek(chunk_start + 12 + length as usize)?;
+        self.png_file.add_chunk(chunk)?;
+        Ok(&raw_type == b"IEND")
+    }
+
+    //Placement rules from the spec, checked before the chunk is parsed so only the chunks already read count
+    fn check_chunk_order(&self, chunk_type: &[u8; 4]) -> Result<(), PngError> {
+        let found = |other: &[u8; 4]| self.png_file.chunks_of_type(*other).next().is_some();
+        let name = String::from_utf8_lossy(chunk_type);
+        let before_plte: [&[u8; 4]; 5] = [b"cHRM", b"gAMA", b"iCCP", b"sBIT", b"sRGB"];
+        let before_idat: [&[u8; 4]; 6] = [b"PLTE", b"tRNS", b"bKGD", b"hIST", b"pHYs", b"sPLT"];
+
+        if before_plte.contains(&chunk_type) && found(b"PLTE") {
+            return Err(PngError::ChunkOrder(format!("{} after PLTE", name)));
+        }
+        if (before_plte.contains(&chunk_type) || before_idat.contains(&chunk_type)) && found(b"IDAT") {
+            return Err(PngError::ChunkOrder(format!("{} after IDAT", name)));
+        }
+        if chunk_type == b"hIST" && !found(b"PLTE") {
+            return Err(PngError::ChunkOrder("hIST before PLTE".to_string()));
+        }
+        let last_was_idat = self.png_file.chunk_list.last().is_some_and(|chunk| matches!(chunk, Chunk::IDAT(..)));
+        if chunk_type == b"IDAT" && found(b"IDAT") && !last_was_idat {
+            return Err(PngError::ChunkOrder("IDAT chunks must be consecutive".to_string()));
         }
         Ok(())
     }
+
+    fn parse_chunk(&mut self, length: u32, key_bytes: &[u8]) -> Result<Chunk, PngError> {
+        let chunk_type = self.chunk_type_map.get(key_bytes)
+            .ok_or_else(|| PngError::UnexpectedChunk(String::from_utf8_lossy(key_bytes).into_owned()))?;
+
+        //These chunks are read differently depending on the colour type/bit depth so they can't be parsed without the header
+        let needs_header = ["PLTE", "IDAT", "bKGD", "tRNS", "sBIT"].contains(&chunk_type.as_str());
+        if needs_header && !self.png_file.chunk_list.iter().any(|p| matches!(p, Chunk::IHDR(..))) {
+            return Err(PngError::ChunkOrder(format!("{} before IHDR", chunk_type)));
+        }
+
+        let chunk = match chunk_type.as_str() {
+            "IHDR" => {
+                let IHDR_chunk = IHDRChunk::new(length, &mut self.png_file)?;
+                let pixels = IHDR_chunk.width() as u64 * IHDR_chunk.height() as u64;
+                if self.png_file.options().max_pixels.is_some_and(|max_pixels| pixels > max_pixels) {
+                    return Err(PngError::LimitExceeded(format!("Image has {} pixels which exceeds the limit", pixels)));
+                }
+                Chunk::IHDR(IHDR_chunk)
+            }
+            "PLTE" => Chunk::PLTE(PLTEChunk::new(length, &mut self.png_file)?),
+            "IDAT" => Chunk::IDAT(IDATChunk::new(length, &mut self.png_file)?),
+            "tIME" => Chunk::tIME(tIMEChunk::new(length, &mut self.png_file)?),
+            "gAMA" => Chunk::gAMA(gAMAChunk::new(length, &mut self.png_file)?),
+            "cHRM" => Chunk::cHRM(cHRMChunk::new(length, &mut self.png_file)?),
+            "bKGD" => Chunk::bKGD(bKGDChunk::new(length, &mut self.png_file)?),
+            "tEXt" => Chunk::tEXt(tEXtChunk::new(length, &mut self.png_file)?),
+            "dSIG" => Chunk::dSIG(dSIGChunk::new(length, &mut self.png_file)?),
+            "eXIf" => Chunk::eXIf(eXIfChunk::new(length, &mut self.png_file)?),
+            "hIST" => Chunk::hIST(hISTChunk::new(length, &mut self.png_file)?),
+            "iCCP" => Chunk::iCCP(iCCPChunk::new(length, &mut self.png_file)?),
+            "iTXt" => Chunk::iTXt(iTXtChunk::new(length, &mut self.png_file)?),
+            "pHYs" => Chunk::pHYs(pHYsChunk::new(length, &mut self.png_file)?),
+            "sBIT" => Chunk::sBIT(sBITChunk::new(length, &mut self.png_file)?),
+            "sPLT" => Chunk::sPLT(sPLTChunk::new(length, &mut self.png_file)?),
+            "sRGB" => Chunk::sRGB(sRGBChunk::new(length, &mut self.png_file)?),
+            "sTER" => Chunk::sTER(sTERChunk::new(length, &mut self.png_file)?),
+            "tRNS" => Chunk::tRNS(tRNSChunk::new(length, &mut self.png_file)?),
+            "zTXt" => Chunk::zTXt(zTXtChunk::new(length, &mut self.png_file)?),
+            "acTL" => Chunk::acTL(acTLChunk::new(length, &mut self.png_file)?),
+            "fcTL" => Chunk::fcTL(fcTLChunk::new(length, &mut self.png_file)?),
+            "fdAT" => Chunk::fdAT(fdATChunk::new(length, &mut self.png_file)?),
+            "sCAL" => Chunk::sCAL(sCALChunk::new(length, &mut self.png_file)?),
+            "IEND" => Chunk::IEND(IENDChunk::new(length, &mut self.png_file)?),
+            _ => return Err(PngError::UnexpectedChunk(chunk_type.clone())),
+        };
+        Ok(chunk)
+    }
 }
@@ -0,0 +1,90 @@
+use crate::png::error::PngError;
+use crate::Png;
+use crate::png::chunks::{acTLChunk, Chunk, ColorType, fcTLChunk};
+use crate::png::decode::{buffer_size, unfilter_rows};
+
+//IDAT is the first frame only when an fcTL comes before it, otherwise it's a default image that isn't part of the animation
+
+#[derive(PartialEq)]
+struct FrameRaster {
+    width: u32,
+    height: u32,
+    rows: Vec<u8>,
+}
+
+impl<'a> Png<'a> {
+    //acTL marks a file as APNG, decoders that don't know it just show the IDAT image
+    pub fn is_animated(&self) -> bool {
+        self.animation_control().is_some()
+    }
+
+    //Frame count from acTL, None for still images
+    pub fn frame_count(&self) -> Option<u32> {
+        self.animation_control().map(acTLChunk::num_frames)
+    }
+
+    pub fn animation_control(&self) -> Option<&acTLChunk> {
+        self.chunk_list.iter().find_map(|chunk| match chunk {
+            Chunk::acTL(acTL_chunk) => Some(acTL_chunk),
+            _ => None,
+        })
+    }
+
+    pub fn frame_controls(&self) -> impl Iterator<Item = &fcTLChunk> {
+        self.chunk_list.iter().filter_map(|chunk| match chunk {
+            Chunk::fcTL(fcTL_chunk) => Some(fcTL_chunk),
+            _ => None,
+        })
+    }
+
+    /*True for an indexed animation where every frame has the same index raster as the one before it, so any change between frames
+    has to come from the palette. APNG only carries one PLTE so palette cycling players swap palettes themselves and this is how to spot
+    those files. Anything that isn't an indexed animation with at least two frames, or that fails to decode, gives false*/
+    pub fn frames_share_indices(&self) -> bool {
+        match self.frame_index_rasters() {
+            Ok(frames) => frames.len() >= 2 && frames.windows(2).all(|pair| pair[0] == pair[1]),
+            Err(_) => false,
+        }
+    }
+
+    //Every frame's defiltered rows along with its size, interlaced frames are left as their passes since only equality matters here
+    fn frame_index_rasters(&self) -> Result<Vec<FrameRaster>, PngError> {
+        let header = self.header()?;
+        if header.color_type() != ColorType::Indexed {
+            return Ok(Vec::new());
+        }
+
+        let mut frames: Vec<(u32, u32, Vec<u8>)> = Vec::new();
+        for chunk in &self.chunk_list {
+            match chunk {
+                Chunk::fcTL(fcTL_chunk) => frames.push((fcTL_chunk.width(), fcTL_chunk.height(), Vec::new())),
+                Chunk::fdAT(fdAT_chunk) => {
+                    let frame = frames.last_mut().ok_or_else(|| PngError::ChunkOrder("fdAT before any fcTL".to_string()))?;
+                    frame.2.extend_from_slice(fdAT_chunk.data());
+                }
+                Chunk::IDAT(IDAT_chunk) => {
+                    if let Some(frame) = frames.last_mut() {
+                        frame.2.extend_from_slice(IDAT_chunk.data());
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let bytes_per_pixel = header.bytes_per_pixel();
+        let mut rasters = Vec::with_capacity(frames.len());
+        for (width, height, compressed) in frames {
+            let inflated = self.inflate(&compressed, self.raster_size_for(width, height)?)?;
+            let mut offset = 0;
+            let mut rows = Vec::new();
+            for pass in self.pass_layout_for(width, height)? {
+                let size = buffer_size(pass.stride + 1, pass.height)?;
+                let filtered = inflated.get(offset..offset + size).ok_or_else(|| PngError::MalformedChunk("Decompressed frame data is too short".to_string()))?;
+                rows.extend(unfilter_rows(filtered, pass.stride, pass.height, bytes_per_pixel, || Ok(()))?);
+                offset += size;
+            }
+            rasters.push(FrameRaster { width, height, rows });
+        }
+        Ok(rasters)
+    }
+}
@@ -0,0 +1,86 @@
+use crate::png::error::PngError;
+use crate::png::crc::crc32;
+use crate::png::png::PNG_SIGNATURE;
+use crate::png::options::DecodeOptions;
+
+/*Borrowing alternative to the owned chunk structs for when the whole file is already in memory (a mmap or a buffer the caller keeps around).
+The big opaque chunks point straight into the source instead of being copied into their own Vec, so a file that's mostly IDAT
+isn't held in memory twice. Everything else is left as its raw type and data for the caller to parse*/
+#[derive(Debug, Clone, Copy)]
+pub enum BorrowedChunk<'d> {
+    IDAT(&'d [u8]),
+    eXIf(&'d [u8]),
+    dSIG(&'d [u8]),
+    iCCP { profile_name: &'d [u8], compression_method: u8, compressed_profile: &'d [u8] },
+    Other { chunk_type: [u8; 4], data: &'d [u8] },
+}
+
+impl<'d> BorrowedChunk<'d> {
+    fn new(chunk_type: [u8; 4], data: &'d [u8]) -> Result<Self, PngError> {
+        let chunk = match &chunk_type {
+            b"IDAT" => BorrowedChunk::IDAT(data),
+            b"eXIf" => BorrowedChunk::eXIf(data),
+            b"dSIG" => BorrowedChunk::dSIG(data),
+            b"iCCP" => {
+                let name_end = data.iter().position(|&byte| byte == 0).ok_or_else(|| PngError::MalformedChunk("iCCP profile name is not null terminated".to_string()))?;
+                let compression_method = *data.get(name_end + 1).ok_or_else(|| PngError::MalformedChunk("iCCP chunk is missing its compression method".to_string()))?;
+                BorrowedChunk::iCCP { profile_name: &data[..name_end], compression_method, compressed_profile: &data[name_end + 2..] }
+            }
+            _ => BorrowedChunk::Other { chunk_type, data },
+        };
+        Ok(chunk)
+    }
+}
+
+//Walks every chunk up to and including IEND checking lengths and CRCs, nothing is copied out of source
+pub fn parse_borrowed(source: &[u8]) -> Result<Vec<BorrowedChunk<'_>>, PngError> {
+    parse_borrowed_with(source, &DecodeOptions::default())
+}
+
+//Same as parse_borrowed with the chunk count and size limits, the CRC toggle and the cancellation flag from options
+pub fn parse_borrowed_with<'d>(source: &'d [u8], options: &DecodeOptions) -> Result<Vec<BorrowedChunk<'d>>, PngError> {
+    if !source.starts_with(&PNG_SIGNATURE) {
+        return Err(PngError::invalid_signature(source));
+    }
+
+    let mut chunks = Vec::new();
+    let mut position = PNG_SIGNATURE.len();
+    loop {
+        if options.is_cancelled() {
+            return Err(PngError::Cancelled);
+        }
+        options.check_chunk_count(chunks.len())?;
+
+        let header = source.get(position..position + 8).ok_or(PngError::MissingIend)?;
+        let length = u32::from_be_bytes([header[0], header[1], header[2], header[3]]);
+        let chunk_type = [header[4], header[5], header[6], header[7]];
+        options.check_chunk_size(length, &chunk_type)?;
+
+        let data_start = position + 8;
+        let crc_bytes = data_start.checked_add(length as usize)
+            .and_then(|crc_start| source.get(crc_start..crc_start.checked_add(4)?))
+            .ok_or_else(|| PngError::UnexpectedEof {
+                chunk_type: Some(String::from_utf8_lossy(&chunk_type).into_owned()),
+                at: data_start,
+                needed: (length as usize).saturating_add(4),
+                available: source.len() - data_start,
+            })?;
+        let crc_start = data_start + length as usize;
+
+        if !options.ignore_crc {
+            let computed = crc32(&source[position + 4..crc_start]);
+            let found = u32::from_be_bytes([crc_bytes[0], crc_bytes[1], crc_bytes[2], crc_bytes[3]]);
+            if computed != found {
+                let chunk_type = String::from_utf8_lossy(&chunk_type).into_owned();
+                return Err(PngError::CrcMismatch { chunk_type, offset: position, expected: computed, found });
+            }
+        }
+
+        chunks.push(BorrowedChunk::new(chunk_type, &source[data_start..crc_start])?);
+        position = crc_start + 4;
+        if &chunk_type == b"IEND" {
+            break;
+        }
+    }
+    Ok(chunks)
+}
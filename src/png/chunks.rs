@@ -1,7 +1,10 @@
-use std::error::Error;
+use crate::png::error::PngError;
 use crate::Png;
+use crate::png::png::DecodeWarning;
+use crate::png::inflate::zlib_decompress;
+use crate::png::decode::{channels, row_bytes};
 
-/*IDHR must be the first chunk in the image and it contains:
+/*IHDR must be the first chunk in the image and it contains:
 - width (4 bytes)
 - height (4 bytes)
 - bit depth (1 byte, values 1, 2, 4, 8, or 16)
@@ -9,15 +12,9 @@ use crate::Png;
 - compression method (1 byte, value 0) Only ever one value
 - filter method (1 byte, value 0) Only ever one value
 - interlace method (1 byte, values 0 "no interlace" or 1 "Adam7 interlace") (13 data bytes total) - Wikipedia */
-/*TODO add CRC to chunks:
-3.4. CRC algorithm
-Chunk CRCs are calculated using standard CRC methods with pre and post conditioning, as defined by ISO 3309 [ISO-3309] or ITU-T V.42 [ITU-V42]. The CRC polynomial employed is
-   x^32+x^26+x^23+x^22+x^16+x^12+x^11+x^10+x^8+x^7+x^5+x^4+x^2+x+1
-The 32-bit CRC register is initialized to all 1's, and then the data from each byte is processed from the least significant bit (1) to the most significant bit (128). After all the data bytes are processed, the CRC register is inverted (its ones complement is taken). This value is transmitted (stored in the file) MSB first. For the purpose of separating into bytes and ordering, the least significant bit of the 32-bit CRC is defined to be the coefficient of the x^31 term.
-Practical calculation of the CRC always employs a precalculated table to greatly accelerate the computation. See Sample CRC Code.*/
-
-#[derive(Debug)]
-pub struct IDHRChunk {
+
+#[derive(Debug, Clone)]
+pub struct IHDRChunk {
     length: u32,
     width: u32,
     height: u32,
@@ -26,13 +23,18 @@ pub struct IDHRChunk {
     compression_method: u8,
     filter_method: u8,
     interlace_method: InterlaceMethod,
-    CRC: Vec<u8>,
+    CRC: u32,
 }
 
-impl IDHRChunk {
-    pub fn new(length: u32, png_file: &mut Png) -> Result<Self, Box<dyn Error>> {
+impl IHDRChunk {
+    pub fn new(length: u32, png_file: &mut Png) -> Result<Self, PngError> {
+        check_length("IHDR", length, 13)?;
         let width = png_file.big_endian_u32()?;
         let height = png_file.big_endian_u32()?;
+        //An empty image has no scanlines at all so there'd be nothing for IDAT to hold
+        if width == 0 || height == 0 {
+            return Err(PngError::MalformedChunk(format!("Image dimensions {}x{} must be non-zero", width, height)));
+        }
         let bit_depth = png_file.get_u8()?;
         let color_type = match png_file.get_u8()? {
             0 => ColorType::Grayscale,
@@ -40,22 +42,105 @@ impl IDHRChunk {
             3 => ColorType::Indexed,
             4 => ColorType::GrayscaleAlpha,
             6 => ColorType::RGBA,
-            _ => return Err("Unknown color type".into()),
+            //1, 5 and 7 are the usual suspects in corrupt files so the value is worth reporting
+            other => return Err(PngError::InvalidColorType(other)),
+        };
+        //Only gray can go below 8 bits without a palette and palette indices can't be 16 bits
+        let allowed_depths: &[u8] = match color_type {
+            ColorType::Grayscale => &[1, 2, 4, 8, 16],
+            ColorType::Indexed => &[1, 2, 4, 8],
+            ColorType::RGB | ColorType::GrayscaleAlpha | ColorType::RGBA => &[8, 16],
         };
+        if !allowed_depths.contains(&bit_depth) {
+            return Err(PngError::InvalidBitDepth { color_type, bit_depth });
+        }
+        //0 is the only method the spec defines for both, checking here beats a confusing failure once decoding starts
         let compression_method = png_file.get_u8()?;
+        if compression_method != 0 {
+            return Err(PngError::UnsupportedCompressionMethod(compression_method));
+        }
         let filter_method = png_file.get_u8()?;
+        if filter_method != 0 {
+            return Err(PngError::UnsupportedFilterMethod(filter_method));
+        }
         let interlace_method = match png_file.get_u8()? {
             0 => InterlaceMethod::None,
             1 => InterlaceMethod::Adam7,
-            _ => return Err("Unknown interlace method".into()),
+            other => return Err(PngError::InvalidInterlaceMethod(other)),
         };
-        let CRC = png_file.get_u32()?;
+        let CRC = png_file.big_endian_u32()?;
 
         Ok(Self{length, width, height, bit_depth, color_type, compression_method, filter_method, interlace_method, CRC})
     }
+
+    //Data field as it would be written to a file, the length, type and CRC are added by build_chunk
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(13);
+        data.extend_from_slice(&self.width.to_be_bytes());
+        data.extend_from_slice(&self.height.to_be_bytes());
+        data.push(self.bit_depth);
+        data.push(match self.color_type {
+            ColorType::Grayscale => 0,
+            ColorType::RGB => 2,
+            ColorType::Indexed => 3,
+            ColorType::GrayscaleAlpha => 4,
+            ColorType::RGBA => 6,
+        });
+        data.push(self.compression_method);
+        data.push(self.filter_method);
+        data.push(match self.interlace_method {
+            InterlaceMethod::None => 0,
+            InterlaceMethod::Adam7 => 1,
+        });
+        data
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn bit_depth(&self) -> u8 {
+        self.bit_depth
+    }
+
+    pub fn color_type(&self) -> ColorType {
+        self.color_type
+    }
+
+    pub fn interlace_method(&self) -> InterlaceMethod {
+        self.interlace_method
+    }
+
+    //Samples per pixel, 1 for indexed since a pixel is a single palette index
+    pub fn channels(&self) -> u8 {
+        channels(self.color_type) as u8
+    }
+
+    pub fn bits_per_pixel(&self) -> usize {
+        self.channels() as usize * self.bit_depth as usize
+    }
+
+    //Rounded up so sub byte depths give 1, this is the distance the filters look back
+    pub fn bytes_per_pixel(&self) -> usize {
+        self.bits_per_pixel().div_ceil(8)
+    }
+
+    //Bytes of pixel data in one row of the whole image, not counting the filter type byte in front of each row
+    pub fn scanline_bytes(&self) -> Result<usize, PngError> {
+        self.row_bytes_for(self.width as usize)
+    }
+
+    //Same for a row narrower than the image (an Adam7 pass or an APNG frame), errors when it can't be addressed on this platform
+    pub(crate) fn row_bytes_for(&self, width: usize) -> Result<usize, PngError> {
+        row_bytes(width, self.bits_per_pixel())
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum ColorType {
     Grayscale,
     RGB,
@@ -64,8 +149,8 @@ pub enum ColorType {
     RGBA,
 }
 
-#[derive(Debug)]
-enum InterlaceMethod {
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum InterlaceMethod {
     None,
     Adam7,
 }
@@ -73,24 +158,45 @@ enum InterlaceMethod {
 
 
 //PLTE Chunks a formed by a series of PaletteEntries
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct PaletteEntry {
     red: u8,
     green: u8,
     blue: u8,
 }
 
-#[derive(Debug)]
+impl PaletteEntry {
+    pub fn rgb(&self) -> [u8; 3] {
+        [self.red, self.green, self.blue]
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct PLTEChunk {
     length: u32,
     entries: Vec<PaletteEntry>,
-    CRC: Vec<u8>,
+    CRC: u32,
 }
 
 impl PLTEChunk {
-    pub fn new(length: u32, png_file: &mut Png) -> Result<Self, Box<dyn Error>> {
-        if length % 3 != 0 {
-            return Err("Invalid chunk length for PLTE".into());
+    pub fn new(length: u32, png_file: &mut Png) -> Result<Self, PngError> {
+        if !length.is_multiple_of(3) {
+            return Err(PngError::MalformedChunk("Invalid chunk length for PLTE".to_string()));
+        }
+
+        let IHDR_chunk = png_file.find_ihdr().ok_or_else(|| PngError::MissingChunk("IHDR".to_string()))?;
+
+        //Indexed images can't address more than 2^bit_depth entries, RGB images may carry a suggested palette of up to 256
+        let max_entries = match IHDR_chunk.color_type {
+            ColorType::Grayscale | ColorType::GrayscaleAlpha => {
+                return Err(PngError::MalformedChunk(format!("PLTE is not allowed for color type {:?}", IHDR_chunk.color_type)));
+            }
+            ColorType::Indexed => 1u32 << IHDR_chunk.bit_depth.min(8),
+            ColorType::RGB | ColorType::RGBA => 256,
+        };
+        let entry_count = length / 3;
+        if entry_count == 0 || entry_count > max_entries {
+            return Err(PngError::MalformedChunk(format!("PLTE has {} entries, expected 1 to {}", entry_count, max_entries)));
         }
 
         let mut entries = Vec::new();
@@ -103,67 +209,97 @@ impl PLTEChunk {
             entries.push(PaletteEntry { red, green, blue });
         }
         
-        let CRC = png_file.get_u32()?;
+        let CRC = png_file.big_endian_u32()?;
 
         Ok(Self{length, entries, CRC})
     }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        self.entries.iter().flat_map(PaletteEntry::rgb).collect()
+    }
+
+    pub fn entries(&self) -> &[PaletteEntry] {
+        &self.entries
+    }
 }
 
 
 //IDAT chunk (Contains all image data compressed and filtered)
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct IDATChunk {
     length: u32,
     data: Vec<u8>,
-    CRC: Vec<u8>,
+    CRC: u32,
 }
 
 impl IDATChunk {
-    pub fn new(length: u32, png_file: &mut Png) -> Result<Self, Box<dyn Error>> {
-        let mut data = Vec::new();
-
-        for _ in 0..length {
-            data.push(png_file.get_u8()?);
+    pub fn new(length: u32, png_file: &mut Png) -> Result<Self, PngError> {
+        //Indexed images can't be resolved without a palette so catch the ordering mistake here rather than when the pixels are expanded
+        let indexed = png_file.find_ihdr().is_some_and(|IHDR_chunk| IHDR_chunk.color_type == ColorType::Indexed);
+        let has_palette = png_file.chunk_list.iter().any(|p| matches!(p, Chunk::PLTE(..)));
+        if indexed && !has_palette {
+            return Err(PngError::ChunkOrder("PLTE must precede IDAT for indexed images".to_string()));
         }
 
-        let CRC = png_file.get_u32()?;
+        let data = png_file.read_bytes(length as usize)?;
+
+        let CRC = png_file.big_endian_u32()?;
 
         Ok(Self{length, data, CRC})
     }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        self.data.clone()
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
 }
 
 
 //IEND
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct IENDChunk {
     length: u32,
-    CRC: Vec<u8>,
+    CRC: u32,
 }
 
 impl IENDChunk {
-    pub fn new(length: u32, png_file: &mut Png) -> Result<Self, Box<dyn Error>> {
-        let CRC = png_file.get_u32()?;
+    pub fn new(length: u32, png_file: &mut Png) -> Result<Self, PngError> {
+        check_length("IEND", length, 0)?;
+        //Files are often cut off right here, everything before is intact so it's worth saying only the trailer is missing
+        if png_file.remaining_bytes() < 4 {
+            return Err(PngError::TruncatedIend);
+        }
+        let CRC = png_file.big_endian_u32()?;
         Ok(Self {length, CRC })
     }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        Vec::new()
+    }
 }
 
 // bKGD
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct bKGDChunk {
     length: u32,
     color: Color,
-    CRC: Vec<u8>,
+    CRC: u32,
 }
 
 impl bKGDChunk {
-    pub fn new(length: u32, png_file: &mut Png) -> Result<Self, Box<dyn Error>> {
-        let IDHR_chunk_opt = png_file.chunk_list.iter_mut().find_map(|p| match p {
-            Chunk::IDHR(chunk_list, ..) => Some(chunk_list),
-            _ => None,
-        });
-        
-        let IDHR_chunk = IDHR_chunk_opt.ok_or("IDHR chunk not found")?; //Code to find the IDHR chunk from our chunk list
-        let color_type = &IDHR_chunk.color_type; //Will always have a happy path
+    pub fn new(length: u32, png_file: &mut Png) -> Result<Self, PngError> {
+        let color_type = png_file.find_ihdr().map(IHDRChunk::color_type).ok_or_else(|| PngError::MissingChunk("IHDR".to_string()))?;
+        let expected_length = match color_type {
+            ColorType::Indexed => 1,
+            ColorType::Grayscale | ColorType::GrayscaleAlpha => 2,
+            ColorType::RGB | ColorType::RGBA => 6,
+        };
+        if length != expected_length {
+            return Err(PngError::MalformedChunk(format!("Invalid chunk length {} for bKGD with color type {:?}", length, color_type)));
+        }
 
         let color = match color_type {
             ColorType::Grayscale | ColorType::GrayscaleAlpha => {
@@ -182,20 +318,33 @@ impl bKGDChunk {
             }
         };
 
-        let CRC = png_file.get_u32()?;
+        let CRC = png_file.big_endian_u32()?;
 
         Ok(Self{length, color, CRC})
     }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        match self.color {
+            Color::Gray(gray) => gray.to_be_bytes().to_vec(),
+            Color::RGB(red, green, blue) => [red, green, blue].iter().flat_map(|sample| sample.to_be_bytes()).collect(),
+            Color::PaletteIndex(palette_index) => vec![palette_index],
+        }
+    }
+
+    pub fn color(&self) -> &Color {
+        &self.color
+    }
 }
 
-#[derive(Debug)]
-enum Color {
+//Gray and RGB samples are at the image's bit depth, not scaled to 16 bits
+#[derive(Debug, Clone)]
+pub enum Color {
     Gray(u16),
     RGB(u16, u16, u16),
     PaletteIndex(u8),
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum BackgroundColor {
     PaletteIndex(u8),
     Grayscale(u16),
@@ -203,26 +352,47 @@ pub enum BackgroundColor {
 }
 
 //Gama chunk
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct gAMAChunk {
     length: u32,
     gamma: u32,
-    CRC: Vec<u8>,
+    CRC: u32,
 }
 
 impl gAMAChunk {
-    pub fn new(length: u32, png_file: &mut Png) -> Result<Self, Box<dyn Error>> {
+    pub fn new(length: u32, png_file: &mut Png) -> Result<Self, PngError> {
+        check_length("gAMA", length, 4)?;
         let gamma = png_file.big_endian_u32()?;
+        //Gamma is stored times 100000, 0 would mean an infinite exponent and anything past 10.0 is almost certainly corrupt
+        if gamma == 0 {
+            return Err(PngError::MalformedChunk("invalid gamma value 0".to_string()));
+        }
+        if gamma > 1_000_000 {
+            png_file.add_warning(DecodeWarning { chunk_type: "gAMA".to_string(), reason: format!("suspicious gamma value {}", gamma) });
+        }
 
-        let CRC = png_file.get_u32()?;
+        let CRC = png_file.big_endian_u32()?;
 
         Ok(Self{length, gamma, CRC})
     }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        self.gamma.to_be_bytes().to_vec()
+    }
+
+    pub fn gamma(&self) -> u32 {
+        self.gamma
+    }
+
+    //The actual exponent, a typical sRGB-ish file stores 45455 which is 0.45455 (1 / 2.2)
+    pub fn gamma_value(&self) -> f64 {
+        self.gamma as f64 / 100000.0
+    }
 }
 
 //cHRM chunk
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct cHRMChunk {
     length: u32,
     white_point_x: u32,
@@ -233,11 +403,12 @@ pub struct cHRMChunk {
     green_y: u32,
     blue_x: u32,
     blue_y: u32,
-    CRC: Vec<u8>,
+    CRC: u32,
 }
 
 impl cHRMChunk {
-    pub fn new(length: u32, png_file: &mut Png) -> Result<Self, Box<dyn Error>> {
+    pub fn new(length: u32, png_file: &mut Png) -> Result<Self, PngError> {
+        check_length("cHRM", length, 32)?;
         let white_point_x = png_file.big_endian_u32()?;
         let white_point_y = png_file.big_endian_u32()?;
         let red_x = png_file.big_endian_u32()?;
@@ -246,102 +417,179 @@ impl cHRMChunk {
         let green_y = png_file.big_endian_u32()?;
         let blue_x = png_file.big_endian_u32()?;
         let blue_y = png_file.big_endian_u32()?;
-        let CRC = png_file.get_u32()?;
+        let CRC = png_file.big_endian_u32()?;
 
         Ok(Self {length, white_point_x, white_point_y, red_x, red_y, green_x, green_y, blue_x, blue_y, CRC})
     }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        [self.white_point_x, self.white_point_y, self.red_x, self.red_y, self.green_x, self.green_y, self.blue_x, self.blue_y]
+            .iter()
+            .flat_map(|value| value.to_be_bytes())
+            .collect()
+    }
+
+    //Each value is stored times 100000, these return the real CIE 1931 x, y coordinates
+    pub fn white_point(&self) -> (f64, f64) {
+        (self.white_point_x as f64 / 100000.0, self.white_point_y as f64 / 100000.0)
+    }
+
+    pub fn red(&self) -> (f64, f64) {
+        (self.red_x as f64 / 100000.0, self.red_y as f64 / 100000.0)
+    }
+
+    pub fn green(&self) -> (f64, f64) {
+        (self.green_x as f64 / 100000.0, self.green_y as f64 / 100000.0)
+    }
+
+    pub fn blue(&self) -> (f64, f64) {
+        (self.blue_x as f64 / 100000.0, self.blue_y as f64 / 100000.0)
+    }
+
+    pub fn chromaticities(&self) -> Chromaticities {
+        let (white_x, white_y) = self.white_point();
+        let (red_x, red_y) = self.red();
+        let (green_x, green_y) = self.green();
+        let (blue_x, blue_y) = self.blue();
+        Chromaticities { white_x, white_y, red_x, red_y, green_x, green_y, blue_x, blue_y }
+    }
+}
+
+//cHRM as real x, y coordinates, sRGB's are white 0.3127, 0.329, red 0.64, 0.33, green 0.3, 0.6 and blue 0.15, 0.06
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Chromaticities {
+    pub white_x: f64,
+    pub white_y: f64,
+    pub red_x: f64,
+    pub red_y: f64,
+    pub green_x: f64,
+    pub green_y: f64,
+    pub blue_x: f64,
+    pub blue_y: f64,
 }
 
 //dSIG 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct dSIGChunk {
     length: u32,
     data: Vec<u8>,
-    CRC: Vec<u8>,
+    CRC: u32,
 }
 
 impl dSIGChunk {
-    pub fn new(length: u32, png_file: &mut Png) -> Result<Self, Box<dyn Error>> {
-        let mut data = Vec::new();
+    pub fn new(length: u32, png_file: &mut Png) -> Result<Self, PngError> {
+        let data = png_file.read_bytes(length as usize)?;
 
-        for _ in 0..length {
-            data.push(png_file.get_u8()?);
-        }
-
-        let CRC = png_file.get_u32()?;
+        let CRC = png_file.big_endian_u32()?;
 
         Ok(Self{length, data, CRC})
     }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        self.data.clone()
+    }
 }
 
 // eXIf chunk
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct eXIfChunk {
     length: u32,
     data: Vec<u8>,
-    CRC: Vec<u8>,
+    CRC: u32,
 }
 
 impl eXIfChunk {
-    pub fn new(length: u32, png_file: &mut Png) -> Result<Self, Box<dyn Error>> {
-        let mut data = Vec::new();
-
-        for _ in 0..length {
-            data.push(png_file.get_u8()?);
-        }
+    pub fn new(length: u32, png_file: &mut Png) -> Result<Self, PngError> {
+        let data = png_file.read_bytes(length as usize)?;
 
-        let CRC = png_file.get_u32()?;
+        let CRC = png_file.big_endian_u32()?;
 
         Ok(Self{length, data, CRC})
     }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        self.data.clone()
+    }
 }
 
 // hIST chunk
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct hISTChunk {
     length: u32,
     data: Vec<u16>,
-    CRC: Vec<u8>,
+    CRC: u32,
 }
 
 impl hISTChunk {
-    pub fn new(length: u32, png_file: &mut Png) -> Result<Self, Box<dyn Error>> {
-        let mut data = Vec::new();
-
-        for _ in 0..length/2 {
-            data.push(png_file.big_endian_u16()?);
+    pub fn new(length: u32, png_file: &mut Png) -> Result<Self, PngError> {
+        //One frequency per palette entry, so the histogram means nothing without the PLTE it describes
+        let palette_entries = png_file.palette()
+            .map(|PLTE_chunk| PLTE_chunk.entries().len())
+            .ok_or_else(|| PngError::ChunkOrder("hIST before PLTE".to_string()))?;
+        if length as usize != palette_entries * 2 {
+            return Err(PngError::MalformedChunk(format!("hIST has {} bytes, expected 2 for each of the {} palette entries", length, palette_entries)));
         }
+        let data = png_file.read_bytes(length as usize)?
+            .chunks_exact(2)
+            .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+            .collect();
 
-        let CRC = png_file.get_u32()?;
+        let CRC = png_file.big_endian_u32()?;
 
         Ok(Self{length, data, CRC})
     }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        self.data.iter().flat_map(|frequency| frequency.to_be_bytes()).collect()
+    }
+
+    //Approximate usage frequency of each palette entry, in palette order
+    pub fn entries(&self) -> &[u16] {
+        &self.data
+    }
 }
 
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct iCCPChunk {
     length: u32,
     profile_name: String,
     compression_method: u8,
     compression_profile: Vec<u8>,
-    CRC: Vec<u8>,
+    CRC: u32,
 }
 
 impl iCCPChunk {
-    pub fn new(length: u32, png_file: &mut Png) -> Result<Self, Box<dyn Error>> {
-        let (profile_name, profile_name_length) = png_file.read_null_terminated_string()?;
+    pub fn new(length: u32, png_file: &mut Png) -> Result<Self, PngError> {
+        let (profile_name, _) = png_file.read_null_terminated_string()?;
         let compression_method = png_file.get_u8()?;
-        let compression_profile = png_file.read_bytes((length - profile_name_length - 1) as usize)?;
+        let compression_profile = png_file.read_to_chunk_end()?;
 
-        let CRC = png_file.get_u32()?;
+        let CRC = png_file.big_endian_u32()?;
 
         Ok(Self {length, profile_name, compression_method, compression_profile, CRC})
     }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut data = self.profile_name.as_bytes().to_vec();
+        data.push(0);
+        data.push(self.compression_method);
+        data.extend_from_slice(&self.compression_profile);
+        data
+    }
+
+    pub fn profile_name(&self) -> &str {
+        &self.profile_name
+    }
+
+    //Raw ICC profile bytes, the chunk only stores them zlib compressed. max_output caps the inflated size, None means no limit
+    pub fn icc_profile(&self, max_output: Option<usize>) -> Result<Vec<u8>, PngError> {
+        inflate_with_method(self.compression_method, &self.compression_profile, max_output)
+    }
 }
 
 //iTxtChunk
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct iTXtChunk {
     length: u32,
     keyword: String,
@@ -349,121 +597,301 @@ pub struct iTXtChunk {
     compression_method: u8,
     language_tag: String,
     translated_keyword: String,
-    text: String,
-    CRC: Vec<u8>,
+    text: Vec<u8>, //Still compressed when compression_flag is 1
+    CRC: u32,
 }
 
 impl iTXtChunk {
-    pub fn new(length: u32, png_file: &mut Png) -> Result<Self, Box<dyn Error>> {
-        let (keyword, keyword_length) = png_file.read_null_terminated_string()?;
+    pub fn new(length: u32, png_file: &mut Png) -> Result<Self, PngError> {
+        let (keyword, _) = png_file.read_null_terminated_string()?;
 
         let compression_flag = png_file.get_u8()?;
+        if compression_flag > 1 {
+            return Err(PngError::MalformedChunk(format!("Invalid iTXt compression flag {}", compression_flag)));
+        }
         let compression_method = png_file.get_u8()?;
 
-        let (language_tag, language_tag_length) = png_file.read_null_terminated_string()?;
+        let (language_tag, _) = png_file.read_null_terminated_string()?;
 
-        let (translated_keyword, translated_keyword_length) = png_file.read_null_terminated_string()?;
+        let (translated_keyword, _) = png_file.read_null_terminated_string()?;
 
-        let mut text_bytes = Vec::new();
-        for _ in 0..(length - keyword_length - language_tag_length - translated_keyword_length - 2) {
-            text_bytes.push(png_file.get_u8()?);
+        let text = png_file.read_to_chunk_end()?;
+        //Uncompressed text can be checked straight away, compressed text is only checked once text() inflates it
+        if compression_flag == 0 {
+            std::str::from_utf8(&text).map_err(|e| PngError::MalformedChunk(format!("iTXt text is not valid UTF-8: {}", e)))?;
         }
-        let text = String::from_utf8(text_bytes)?;
 
-        let CRC = png_file.get_u32()?;
+        let CRC = png_file.big_endian_u32()?;
 
         Ok(Self{length, keyword, compression_flag, compression_method, language_tag, translated_keyword, text, CRC})
     }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut data = self.keyword.as_bytes().to_vec();
+        data.push(0);
+        data.push(self.compression_flag);
+        data.push(self.compression_method);
+        data.extend_from_slice(self.language_tag.as_bytes());
+        data.push(0);
+        data.extend_from_slice(self.translated_keyword.as_bytes());
+        data.push(0);
+        data.extend_from_slice(&self.text);
+        data
+    }
+
+    pub fn keyword(&self) -> &str {
+        &self.keyword
+    }
+
+    pub fn language_tag(&self) -> &str {
+        &self.language_tag
+    }
+
+    pub fn translated_keyword(&self) -> &str {
+        &self.translated_keyword
+    }
+
+    //iTXt text is UTF-8 either way, it just has to be inflated first when the compression flag is set (up to max_output bytes)
+    pub fn text(&self, max_output: Option<usize>) -> Result<String, PngError> {
+        let bytes = if self.compression_flag == 1 {
+            inflate_with_method(self.compression_method, &self.text, max_output)?
+        } else {
+            self.text.clone()
+        };
+        String::from_utf8(bytes).map_err(Into::into)
+    }
 }
 
 //pHYs Chunk
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct pHYsChunk {
     length: u32,
     pixels_per_unit_x_axis: u32,
     pixels_per_unit_y_axis: u32,
-    unit_specifier: u8,
-    CRC: Vec<u8>,
+    unit_specifier: PhysicalUnit,
+    CRC: u32,
 }
 
 impl pHYsChunk {
-    pub fn new(length: u32, png_file: &mut Png) -> Result<Self, Box<dyn Error>> {
+    pub fn new(length: u32, png_file: &mut Png) -> Result<Self, PngError> {
+        check_length("pHYs", length, 9)?;
         let pixels_per_unit_x_axis = png_file.big_endian_u32()?;
         let pixels_per_unit_y_axis = png_file.big_endian_u32()?;
-        let unit_specifier = png_file.get_u8()?;
-        let CRC = png_file.get_u32()?;
+        let unit_specifier = match png_file.get_u8()? {
+            0 => PhysicalUnit::Unknown,
+            1 => PhysicalUnit::Meter,
+            other => return Err(PngError::MalformedChunk(format!("Unknown pHYs unit {}", other))),
+        };
+        let CRC = png_file.big_endian_u32()?;
 
         Ok(Self{length, pixels_per_unit_x_axis, pixels_per_unit_y_axis, unit_specifier, CRC})
     }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(9);
+        data.extend_from_slice(&self.pixels_per_unit_x_axis.to_be_bytes());
+        data.extend_from_slice(&self.pixels_per_unit_y_axis.to_be_bytes());
+        data.push(match self.unit_specifier {
+            PhysicalUnit::Unknown => 0,
+            PhysicalUnit::Meter => 1,
+        });
+        data
+    }
+
+    pub fn pixels_per_unit_x(&self) -> u32 {
+        self.pixels_per_unit_x_axis
+    }
+
+    pub fn pixels_per_unit_y(&self) -> u32 {
+        self.pixels_per_unit_y_axis
+    }
+
+    pub fn unit_specifier(&self) -> PhysicalUnit {
+        self.unit_specifier
+    }
+
+    //Pixels per metre times 0.0254 metres per inch, None when the unit is unknown or a value is 0
+    pub fn dpi(&self) -> Option<(f64, f64)> {
+        if self.unit_specifier != PhysicalUnit::Meter || self.pixels_per_unit_x_axis == 0 || self.pixels_per_unit_y_axis == 0 {
+            return None;
+        }
+        const METRES_PER_INCH: f64 = 0.0254;
+        Some((self.pixels_per_unit_x_axis as f64 * METRES_PER_INCH, self.pixels_per_unit_y_axis as f64 * METRES_PER_INCH))
+    }
+}
+
+//Unknown means the pixel counts only give the aspect ratio
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PhysicalUnit {
+    Unknown,
+    Meter,
 }
 
 //sBIT
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct sBITChunk {
     length: u32,
-    data: Vec<u8>,
-    CRC: Vec<u8>,
+    significant_bits: SignificantBits,
+    CRC: u32,
 }
 
 impl sBITChunk {
-    pub fn new(length: u32, png_file: &mut Png) -> Result<Self, Box<dyn Error>> {
-        let mut data = Vec::new();
-
-        for _ in 0..length {
-            data.push(png_file.get_u8()?);
+    pub fn new(length: u32, png_file: &mut Png) -> Result<Self, PngError> {
+        let IHDR_chunk = png_file.find_ihdr().ok_or_else(|| PngError::MissingChunk("IHDR".to_string()))?;
+        let color_type = IHDR_chunk.color_type;
+        //Indexed images describe their palette entries which are always 8 bits per channel
+        let max_bits = if color_type == ColorType::Indexed { 8 } else { IHDR_chunk.bit_depth };
+
+        let expected_length = match color_type {
+            ColorType::Grayscale => 1,
+            ColorType::GrayscaleAlpha => 2,
+            ColorType::RGB | ColorType::Indexed => 3,
+            ColorType::RGBA => 4,
+        };
+        if length != expected_length {
+            return Err(PngError::MalformedChunk(format!("Invalid chunk length {} for sBIT with color type {:?}", length, color_type)));
+        }
+        let bits = png_file.read_bytes(length as usize)?;
+        if bits.iter().any(|&b| b == 0 || b > max_bits) {
+            return Err(PngError::MalformedChunk(format!("sBIT values {:?} must be between 1 and {}", bits, max_bits)));
         }
 
-        let CRC = png_file.get_u32()?;
+        let significant_bits = match color_type {
+            ColorType::Grayscale => SignificantBits::Grayscale { gray: bits[0] },
+            ColorType::GrayscaleAlpha => SignificantBits::GrayscaleAlpha { gray: bits[0], alpha: bits[1] },
+            ColorType::RGB | ColorType::Indexed => SignificantBits::Rgb { red: bits[0], green: bits[1], blue: bits[2] },
+            ColorType::RGBA => SignificantBits::Rgba { red: bits[0], green: bits[1], blue: bits[2], alpha: bits[3] },
+        };
+        let CRC = png_file.big_endian_u32()?;
 
-        Ok(Self{length, data, CRC})
+        Ok(Self{length, significant_bits, CRC})
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        match self.significant_bits {
+            SignificantBits::Grayscale { gray } => vec![gray],
+            SignificantBits::GrayscaleAlpha { gray, alpha } => vec![gray, alpha],
+            SignificantBits::Rgb { red, green, blue } => vec![red, green, blue],
+            SignificantBits::Rgba { red, green, blue, alpha } => vec![red, green, blue, alpha],
+        }
+    }
+
+    pub fn significant_bits(&self) -> &SignificantBits {
+        &self.significant_bits
     }
 }
 
+//How many of each channel's bits were significant in the original data before it was scaled up to the PNG's bit depth
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SignificantBits {
+    Grayscale { gray: u8 },
+    GrayscaleAlpha { gray: u8, alpha: u8 },
+    Rgb { red: u8, green: u8, blue: u8 }, //Also used for indexed images, where it applies to the palette
+    Rgba { red: u8, green: u8, blue: u8, alpha: u8 },
+}
+
 //sPLT Chunk (I hate these useless chunks)
 
 //sPLT is very similar to PLTE
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct sPLTEntry {
     red: u16,
     green: u16,
     blue: u16,
     alpha: u16,
-    frequency: u32,
+    frequency: u16,
 }
 
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct sPLTChunk {
     length: u32,
     palette_name: String,
     sample_depth: u8,
     entries: Vec<sPLTEntry>,
-    CRC: Vec<u8>,
+    CRC: u32,
 }
 
 impl sPLTChunk {
-    pub fn new(length: u32, png_file: &mut Png) -> Result<Self, Box<dyn Error>> {
-        let (palette_name, name_length) = png_file.read_null_terminated_string()?;
+    pub fn new(length: u32, png_file: &mut Png) -> Result<Self, PngError> {
+        let (palette_name, _) = png_file.read_null_terminated_string()?;
         let sample_depth = png_file.get_u8()?;
+        if sample_depth != 8 && sample_depth != 16 {
+            return Err(PngError::MalformedChunk(format!("Invalid sPLT sample depth {}", sample_depth)));
+        }
 
         let mut entries = Vec::new();
         let entry_length = if sample_depth == 8 { 6 } else { 10 };
-        let num_entries = (length - name_length - 2) / entry_length;
+        let remaining = png_file.remaining_in_chunk()?;
+        if remaining % entry_length != 0 {
+            return Err(PngError::MalformedChunk(format!("sPLT entries don't fit evenly in {} bytes", remaining)));
+        }
+        let num_entries = remaining / entry_length;
 
         for _ in 0..num_entries { //We have to adjust for the sample depth value
             let red = if sample_depth == 8 { png_file.get_u8()? as u16 } else { png_file.big_endian_u16()? };
             let green = if sample_depth == 8 { png_file.get_u8()? as u16 } else { png_file.big_endian_u16()? };
             let blue = if sample_depth == 8 { png_file.get_u8()? as u16 } else { png_file.big_endian_u16()? };
             let alpha = if sample_depth == 8 { png_file.get_u8()? as u16 } else { png_file.big_endian_u16()? };
-            let frequency = png_file.big_endian_u32()?;
+            let frequency = png_file.big_endian_u16()?;
 
             entries.push(sPLTEntry { red, green, blue, alpha, frequency });
         }
 
-        let CRC = png_file.get_u32()?;
+        let CRC = png_file.big_endian_u32()?;
 
         Ok(Self{length, palette_name, sample_depth, entries, CRC})
     }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut data = self.palette_name.as_bytes().to_vec();
+        data.push(0);
+        data.push(self.sample_depth);
+        for entry in &self.entries {
+            for sample in [entry.red, entry.green, entry.blue, entry.alpha] {
+                if self.sample_depth == 8 {
+                    data.push(sample as u8);
+                } else {
+                    data.extend_from_slice(&sample.to_be_bytes());
+                }
+            }
+            data.extend_from_slice(&entry.frequency.to_be_bytes());
+        }
+        data
+    }
+
+    //8 bit samples are scaled up to 16 bits (v * 257 maps 255 to 65535) so every palette uses the same representation
+    pub fn suggested_palette(&self) -> SuggestedPalette {
+        let scale = |sample: u16| if self.sample_depth == 8 { sample * 257 } else { sample };
+        let entries = self.entries.iter()
+            .map(|entry| SuggestedPaletteEntry {
+                red: scale(entry.red),
+                green: scale(entry.green),
+                blue: scale(entry.blue),
+                alpha: scale(entry.alpha),
+                frequency: entry.frequency,
+            })
+            .collect();
+
+        SuggestedPalette { name: self.palette_name.clone(), sample_depth: self.sample_depth, entries }
+    }
+}
+
+//Public view of an sPLT chunk for quantization, samples are always 16 bit regardless of the stored sample depth
+#[derive(Debug, Clone)]
+pub struct SuggestedPalette {
+    pub name: String,
+    pub sample_depth: u8,
+    pub entries: Vec<SuggestedPaletteEntry>,
+}
+
+#[derive(Debug, Clone)]
+pub struct SuggestedPaletteEntry {
+    pub red: u16,
+    pub green: u16,
+    pub blue: u16,
+    pub alpha: u16,
+    pub frequency: u16,
 }
 
 /* sRGB
@@ -473,7 +901,7 @@ The following values are defined for the rendering intent:
 2: Saturation
 3: Absolute colorimetric*/
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum RenderingIntent {
     Perceptual,
     RelativeColorimetric,
@@ -481,67 +909,114 @@ pub enum RenderingIntent {
     AbsoluteColorimetric,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct sRGBChunk {
     length: u32,
     rendering_intent: RenderingIntent,
-    CRC: Vec<u8>,
+    CRC: u32,
 }
 
 impl sRGBChunk {
-    pub fn new(length: u32, png_file: &mut Png) -> Result<Self, Box<dyn Error>> {
+    pub fn new(length: u32, png_file: &mut Png) -> Result<Self, PngError> {
+        check_length("sRGB", length, 1)?;
         let rendering_intent = match png_file.get_u8()? {
             0 => RenderingIntent::Perceptual,
             1 => RenderingIntent::RelativeColorimetric,
             2 => RenderingIntent::Saturation,
             3 => RenderingIntent::AbsoluteColorimetric,
-            _ => return Err("Invalid value for rendering intent".into()),
+            _ => return Err(PngError::MalformedChunk("Invalid value for rendering intent".to_string())),
         };
-        let CRC = png_file.get_u32()?;
+        let CRC = png_file.big_endian_u32()?;
 
         Ok(Self { length, rendering_intent, CRC })
     }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        vec![match self.rendering_intent {
+            RenderingIntent::Perceptual => 0,
+            RenderingIntent::RelativeColorimetric => 1,
+            RenderingIntent::Saturation => 2,
+            RenderingIntent::AbsoluteColorimetric => 3,
+        }]
+    }
 }
 
 // sTER extremely odd chunk which has little doccumentation but listed on wikipedia so I've decided to implement it
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct sTERChunk {
     length: u32,
-    stereo_mode: u8,
-    CRC: Vec<u8>,
+    stereo_mode: StereoLayout,
+    CRC: u32,
 }
 
 impl sTERChunk {
-    pub fn new(length: u32, png_file: &mut Png) -> Result<Self, Box<dyn Error>> {
-        let stereo_mode = png_file.get_u8()?;
-        let CRC = png_file.get_u32()?;
+    pub fn new(length: u32, png_file: &mut Png) -> Result<Self, PngError> {
+        check_length("sTER", length, 1)?;
+        let stereo_mode = match png_file.get_u8()? {
+            0 => StereoLayout::CrossFuse,
+            1 => StereoLayout::DivergingFuse,
+            other => return Err(PngError::InvalidStereoMode(other)),
+        };
+        let CRC = png_file.big_endian_u32()?;
 
         Ok(Self { length, stereo_mode, CRC })
     }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        vec![match self.stereo_mode {
+            StereoLayout::CrossFuse => 0,
+            StereoLayout::DivergingFuse => 1,
+        }]
+    }
+
+    pub fn stereo_mode(&self) -> StereoLayout {
+        self.stereo_mode
+    }
+}
+
+//Which eye each half of the side by side image is for, cross fuse has the right eye image on the left
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StereoLayout {
+    CrossFuse,
+    DivergingFuse,
 }
 
 //tEXt Chunk some improvements need to be made here
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct tEXtChunk {
     length: u32,
     keyword: String,
     text: String,
-    CRC: Vec<u8>,
+    CRC: u32,
 }
 
 impl tEXtChunk {
-    pub fn new(length: u32, png_file: &mut Png) -> Result<Self, Box<dyn Error>> {
-        let (keyword, keyword_length) = png_file.read_null_terminated_string()?;
-        let text_length = length - keyword_length - 1; // Subtract 1 for the null character
-        let text = png_file.get_string(text_length as usize)?;
-        let CRC = png_file.get_u32()?;
+    pub fn new(length: u32, png_file: &mut Png) -> Result<Self, PngError> {
+        let keyword = latin1_to_string(&png_file.read_null_terminated_bytes()?);
+        let text = latin1_to_string(&png_file.read_to_chunk_end()?);
+        let CRC = png_file.big_endian_u32()?;
 
         Ok(Self { length, keyword, text, CRC })
     }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut data = string_to_latin1(&self.keyword);
+        data.push(0);
+        data.extend(string_to_latin1(&self.text));
+        data
+    }
+
+    pub fn keyword(&self) -> &str {
+        &self.keyword
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
 }
 
 //tIME Chunk
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct tIMEChunk {
     length: u32,
     year: u16,
@@ -550,81 +1025,473 @@ pub struct tIMEChunk {
     hour: u8,
     minute: u8,
     second: u8,
-    CRC: Vec<u8>,
+    CRC: u32,
 }
 
 impl tIMEChunk {
-    pub fn new(length: u32, png_file: &mut Png) -> Result<Self, Box<dyn Error>> {
+    pub fn new(length: u32, png_file: &mut Png) -> Result<Self, PngError> {
+        check_length("tIME", length, 7)?;
         let year = png_file.big_endian_u16()?;
         let month = png_file.get_u8()?;
         let day = png_file.get_u8()?;
         let hour = png_file.get_u8()?;
         let minute = png_file.get_u8()?;
         let second = png_file.get_u8()?;
-        let CRC = png_file.get_u32()?;
+        //Second goes up to 60 to allow for leap seconds
+        let ranges = [("month", month, 1..=12), ("day", day, 1..=31), ("hour", hour, 0..=23), ("minute", minute, 0..=59), ("second", second, 0..=60)];
+        if let Some((field, value, _)) = ranges.iter().find(|(_, value, range)| !range.contains(value)) {
+            return Err(PngError::InvalidTime(format!("{} {}", field, value)));
+        }
+        let CRC = png_file.big_endian_u32()?;
 
         Ok(Self{length, year, month, day, hour, minute, second, CRC})
     }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut data = self.year.to_be_bytes().to_vec();
+        data.extend_from_slice(&[self.month, self.day, self.hour, self.minute, self.second]);
+        data
+    }
+
+    //(year, month, day, hour, minute, second) in UTC
+    pub fn to_tuple(&self) -> (u16, u8, u8, u8, u8, u8) {
+        (self.year, self.month, self.day, self.hour, self.minute, self.second)
+    }
 }
 
 //tRNS chunk
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct tRNSChunk {
     length: u32,
-    transparency_data: Vec<u8>,
-    CRC: Vec<u8>,
+    transparency: TransparencyInfo,
+    CRC: u32,
 }
 
 impl tRNSChunk {
-    pub fn new(length: u32, png_file: &mut Png) -> Result<Self, Box<dyn Error>> {
-        let mut transparency_data = Vec::new();
-        for _ in 0..length {
-            transparency_data.push(png_file.get_u8()?);
+    pub fn new(length: u32, png_file: &mut Png) -> Result<Self, PngError> {
+        let color_type = png_file.find_ihdr().map(IHDRChunk::color_type).ok_or_else(|| PngError::MissingChunk("IHDR".to_string()))?;
+
+        //Same layout as bKGD except indexed images get an alpha per palette entry, images with an alpha channel don't need it at all
+        let transparency = match (color_type, length) {
+            (ColorType::Indexed, _) => {
+                //Like hIST the alphas are per palette entry, there can be fewer than entries but not more
+                let palette_entries = png_file.palette()
+                    .map(|PLTE_chunk| PLTE_chunk.entries().len())
+                    .ok_or_else(|| PngError::ChunkOrder("tRNS before PLTE".to_string()))?;
+                if length as usize > palette_entries {
+                    return Err(PngError::MalformedChunk(format!("tRNS has {} entries but the palette only has {}", length, palette_entries)));
+                }
+                TransparencyInfo::Indexed(png_file.read_bytes(length as usize)?)
+            }
+            (ColorType::Grayscale, 2) => TransparencyInfo::Grayscale(png_file.big_endian_u16()?),
+            (ColorType::RGB, 6) => {
+                let red = png_file.big_endian_u16()?;
+                let green = png_file.big_endian_u16()?;
+                let blue = png_file.big_endian_u16()?;
+                TransparencyInfo::Rgb(red, green, blue)
+            }
+            (ColorType::GrayscaleAlpha | ColorType::RGBA, _) => {
+                return Err(PngError::MalformedChunk(format!("tRNS is not allowed for color type {:?}", color_type)));
+            }
+            _ => return Err(PngError::MalformedChunk(format!("Invalid chunk length {} for tRNS with color type {:?}", length, color_type))),
+        };
+        let CRC = png_file.big_endian_u32()?;
+
+        Ok(Self { length, transparency, CRC })
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        match &self.transparency {
+            TransparencyInfo::Indexed(alphas) => alphas.clone(),
+            TransparencyInfo::Grayscale(gray) => gray.to_be_bytes().to_vec(),
+            TransparencyInfo::Rgb(red, green, blue) => [red, green, blue].iter().flat_map(|sample| sample.to_be_bytes()).collect(),
         }
-        let CRC = png_file.get_u32()?;
+    }
 
-        Ok(Self { length, transparency_data, CRC })
+    pub fn transparency(&self) -> &TransparencyInfo {
+        &self.transparency
     }
 }
 
+//Gray and RGB keys are at the image's bit depth like bKGD, a pixel exactly matching the key is fully transparent
+#[derive(Debug, Clone)]
+pub enum TransparencyInfo {
+    Indexed(Vec<u8>), //Alpha for the first n palette entries, the rest are opaque
+    Grayscale(u16),
+    Rgb(u16, u16, u16),
+}
+
 
 //Compressed Text chunk zTXt
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct zTXtChunk {
     length: u32,
     keyword: String,
     compression_method: u8,
     compressed_text: Vec<u8>,
-    CRC: Vec<u8>,
+    CRC: u32,
 }
 
 impl zTXtChunk {
-    pub fn new(length: u32, png_file: &mut Png) -> Result<Self, Box<dyn Error>> {
-        let mut keyword = String::new();
-        //I won't declare a function for this as it's only used once
-        loop {
-            let c = png_file.get_u8()? as char;
-            if c == '\0' {
-                break;
-            }
-            keyword.push(c);
-        }
+    pub fn new(length: u32, png_file: &mut Png) -> Result<Self, PngError> {
+        let keyword = latin1_to_string(&png_file.read_null_terminated_bytes()?);
         let compression_method = png_file.get_u8()?;
-        let mut compressed_text = Vec::new();
-        for _ in 0..length - keyword.len() as u32 - 2 {
-            compressed_text.push(png_file.get_u8()?);
-        }
-        let CRC = png_file.get_u32()?;
+        let compressed_text = png_file.read_to_chunk_end()?;
+        let CRC = png_file.big_endian_u32()?;
 
         Ok(Self{length, keyword, compression_method, compressed_text, CRC})
     }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut data = string_to_latin1(&self.keyword);
+        data.push(0);
+        data.push(self.compression_method);
+        data.extend_from_slice(&self.compressed_text);
+        data
+    }
+
+    pub fn keyword(&self) -> &str {
+        &self.keyword
+    }
+
+    //max_output caps the inflated size, None means no limit
+    pub fn decompressed_text(&self, max_output: Option<usize>) -> Result<String, PngError> {
+        let bytes = inflate_with_method(self.compression_method, &self.compressed_text, max_output)?;
+        Ok(latin1_to_string(&bytes))
+    }
+}
+
+//tEXt and zTXt are Latin-1 (ISO-8859-1) whose 256 characters are the first 256 Unicode code points, so every byte maps straight across
+fn latin1_to_string(bytes: &[u8]) -> String {
+    bytes.iter().map(|&byte| byte as char).collect()
+}
+
+//Inverse of latin1_to_string, every char came from a single byte so the cast is lossless
+fn string_to_latin1(text: &str) -> Vec<u8> {
+    text.chars().map(|character| character as u8).collect()
+}
+
+//For the chunks whose fields add up to a fixed size, checked before reading so a short chunk doesn't read into the next one
+fn check_length(chunk_type: &str, length: u32, expected: u32) -> Result<(), PngError> {
+    if length != expected {
+        return Err(PngError::MalformedChunk(format!("Invalid chunk length {} for {}", length, chunk_type)));
+    }
+    Ok(())
+}
+
+//0 (zlib) is the only compression method the spec defines, anything else is checked here rather than fed to the inflater
+fn inflate_with_method(compression_method: u8, data: &[u8], max_output: Option<usize>) -> Result<Vec<u8>, PngError> {
+    if compression_method != 0 {
+        return Err(PngError::UnsupportedCompressionMethod(compression_method));
+    }
+    zlib_decompress(data, true, max_output)
+}
+
+//acTL Animation control chunk from APNG, num_plays of 0 means the animation loops forever
+#[derive(Debug, Clone)]
+pub struct acTLChunk {
+    length: u32,
+    num_frames: u32,
+    num_plays: u32,
+    CRC: u32,
+}
+
+impl acTLChunk {
+    pub fn new(length: u32, png_file: &mut Png) -> Result<Self, PngError> {
+        check_length("acTL", length, 8)?;
+        let num_frames = png_file.big_endian_u32()?;
+        let num_plays = png_file.big_endian_u32()?;
+        let CRC = png_file.big_endian_u32()?;
+
+        Ok(Self{length, num_frames, num_plays, CRC})
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut data = self.num_frames.to_be_bytes().to_vec();
+        data.extend_from_slice(&self.num_plays.to_be_bytes());
+        data
+    }
+
+    pub fn num_frames(&self) -> u32 {
+        self.num_frames
+    }
+
+    pub fn loops_forever(&self) -> bool {
+        self.num_plays == 0
+    }
+
+    //None means the animation should loop forever
+    pub fn play_count(&self) -> Option<u32> {
+        if self.loops_forever() { None } else { Some(self.num_plays) }
+    }
+}
+
+/*fcTL frame control chunk from APNG, one before each frame's data:
+- sequence number (4 bytes, shared with fdAT so the chunks can be put back in order)
+- width, height, x offset, y offset of the frame within the canvas (4 bytes each)
+- delay numerator and denominator (2 bytes each, a denominator of 0 means 100)
+- dispose op and blend op (1 byte each) 26 bytes total*/
+#[derive(Debug, Clone)]
+pub struct fcTLChunk {
+    length: u32,
+    sequence_number: u32,
+    width: u32,
+    height: u32,
+    x_offset: u32,
+    y_offset: u32,
+    delay_num: u16,
+    delay_den: u16,
+    dispose_op: DisposeOp,
+    blend_op: BlendOp,
+    CRC: u32,
+}
+
+impl fcTLChunk {
+    pub fn new(length: u32, png_file: &mut Png) -> Result<Self, PngError> {
+        check_length("fcTL", length, 26)?;
+        let sequence_number = png_file.big_endian_u32()?;
+        let width = png_file.big_endian_u32()?;
+        let height = png_file.big_endian_u32()?;
+        let x_offset = png_file.big_endian_u32()?;
+        let y_offset = png_file.big_endian_u32()?;
+        let delay_num = png_file.big_endian_u16()?;
+        let delay_den = png_file.big_endian_u16()?;
+        let dispose_op = match png_file.get_u8()? {
+            0 => DisposeOp::None,
+            1 => DisposeOp::Background,
+            2 => DisposeOp::Previous,
+            other => return Err(PngError::MalformedChunk(format!("Unknown fcTL dispose op {}", other))),
+        };
+        let blend_op = match png_file.get_u8()? {
+            0 => BlendOp::Source,
+            1 => BlendOp::Over,
+            other => return Err(PngError::MalformedChunk(format!("Unknown fcTL blend op {}", other))),
+        };
+        let CRC = png_file.big_endian_u32()?;
+
+        Ok(Self{length, sequence_number, width, height, x_offset, y_offset, delay_num, delay_den, dispose_op, blend_op, CRC})
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(26);
+        for value in [self.sequence_number, self.width, self.height, self.x_offset, self.y_offset] {
+            data.extend_from_slice(&value.to_be_bytes());
+        }
+        data.extend_from_slice(&self.delay_num.to_be_bytes());
+        data.extend_from_slice(&self.delay_den.to_be_bytes());
+        data.push(match self.dispose_op {
+            DisposeOp::None => 0,
+            DisposeOp::Background => 1,
+            DisposeOp::Previous => 2,
+        });
+        data.push(match self.blend_op {
+            BlendOp::Source => 0,
+            BlendOp::Over => 1,
+        });
+        data
+    }
+
+    pub fn sequence_number(&self) -> u32 {
+        self.sequence_number
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn offset(&self) -> (u32, u32) {
+        (self.x_offset, self.y_offset)
+    }
+
+    //Frame delay in seconds
+    pub fn delay(&self) -> f64 {
+        let denominator = if self.delay_den == 0 { 100 } else { self.delay_den };
+        self.delay_num as f64 / denominator as f64
+    }
+
+    pub fn dispose_op(&self) -> DisposeOp {
+        self.dispose_op
+    }
+
+    pub fn blend_op(&self) -> BlendOp {
+        self.blend_op
+    }
+}
+
+//What happens to the frame's area before the next frame is drawn
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DisposeOp {
+    None, //Left as it is
+    Background, //Cleared to fully transparent black
+    Previous, //Put back to what it was before this frame
+}
+
+//Source replaces the area including alpha, Over alpha blends the frame onto it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BlendOp {
+    Source,
+    Over,
+}
+
+//fdAT frame data chunk from APNG, a sequence number followed by zlib data exactly like IDAT
+#[derive(Debug, Clone)]
+pub struct fdATChunk {
+    length: u32,
+    sequence_number: u32,
+    data: Vec<u8>,
+    CRC: u32,
+}
+
+impl fdATChunk {
+    pub fn new(length: u32, png_file: &mut Png) -> Result<Self, PngError> {
+        if length < 4 {
+            return Err(PngError::MalformedChunk("fdAT chunk is too short".to_string()));
+        }
+        let sequence_number = png_file.big_endian_u32()?;
+        let data = png_file.read_to_chunk_end()?;
+        let CRC = png_file.big_endian_u32()?;
+
+        Ok(Self{length, sequence_number, data, CRC})
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut data = self.sequence_number.to_be_bytes().to_vec();
+        data.extend_from_slice(&self.data);
+        data
+    }
+
+    pub fn sequence_number(&self) -> u32 {
+        self.sequence_number
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+/*sCAL physical scale of the image subject:
+- unit specifier (1 byte, 1 meter or 2 radian)
+- pixel width (ASCII floating-point, null terminated)
+- pixel height (ASCII floating-point, runs to the end of the chunk)*/
+#[derive(Debug, Clone)]
+pub enum ScaleUnit {
+    Meter,
+    Radian,
+}
+
+#[derive(Debug, Clone)]
+pub struct sCALChunk {
+    length: u32,
+    unit: ScaleUnit,
+    width_per_pixel: f64,
+    height_per_pixel: f64,
+    width_text: String, //Kept as written so serializing doesn't reformat the numbers
+    height_text: String,
+    CRC: u32,
+}
+
+impl sCALChunk {
+    pub fn new(length: u32, png_file: &mut Png) -> Result<Self, PngError> {
+        let unit = match png_file.get_u8()? {
+            1 => ScaleUnit::Meter,
+            2 => ScaleUnit::Radian,
+            _ => return Err(PngError::MalformedChunk("Unknown sCAL unit".to_string())),
+        };
+        let (width_string, _) = png_file.read_null_terminated_string()?;
+        let width_per_pixel = parse_ascii_float(width_string.as_bytes())?;
+        let height_bytes = png_file.read_to_chunk_end()?;
+        let height_per_pixel = parse_ascii_float(&height_bytes)?;
+        //parse_ascii_float only lets ASCII through so this can't lose anything
+        let width_text = width_string;
+        let height_text = String::from_utf8_lossy(&height_bytes).into_owned();
+        let CRC = png_file.big_endian_u32()?;
+
+        Ok(Self{length, unit, width_per_pixel, height_per_pixel, width_text, height_text, CRC})
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut data = vec![match self.unit {
+            ScaleUnit::Meter => 1,
+            ScaleUnit::Radian => 2,
+        }];
+        data.extend_from_slice(self.width_text.as_bytes());
+        data.push(0);
+        data.extend_from_slice(self.height_text.as_bytes());
+        data
+    }
+
+    pub fn unit(&self) -> &ScaleUnit {
+        &self.unit
+    }
+
+    pub fn width_per_pixel(&self) -> f64 {
+        self.width_per_pixel
+    }
+
+    pub fn height_per_pixel(&self) -> f64 {
+        self.height_per_pixel
+    }
+}
+
+//The spec only allows plain decimal/exponent notation so things like "inf" or "NaN" which Rust would accept are rejected, values must also be positive
+fn parse_ascii_float(bytes: &[u8]) -> Result<f64, PngError> {
+    if bytes.is_empty() || !bytes.iter().all(|b| b.is_ascii_digit() || b"+-.eE".contains(b)) {
+        return Err(PngError::MalformedChunk("Invalid ASCII floating-point value".to_string()));
+    }
+    let value: f64 = std::str::from_utf8(bytes)
+        .ok()
+        .and_then(|text| text.parse().ok())
+        .ok_or_else(|| PngError::MalformedChunk("Invalid ASCII floating-point value".to_string()))?;
+    if value <= 0.0 {
+        return Err(PngError::MalformedChunk("ASCII floating-point value must be positive".to_string()));
+    }
+    Ok(value)
+}
+
+//Chunk kept as raw bytes, either because its type isn't known or because the caller asked for it not to be parsed
+#[derive(Debug, Clone)]
+pub struct UnknownChunk {
+    length: u32,
+    chunk_type: [u8; 4],
+    data: Vec<u8>,
+    CRC: u32,
+}
+
+impl UnknownChunk {
+    pub fn new(length: u32, chunk_type: [u8; 4], png_file: &mut Png) -> Result<Self, PngError> {
+        let data = png_file.read_bytes(length as usize)?;
+        let CRC = png_file.big_endian_u32()?;
+
+        Ok(Self{length, chunk_type, data, CRC})
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        self.data.clone()
+    }
+
+    pub fn chunk_type(&self) -> [u8; 4] {
+        self.chunk_type
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    //Stored CRC as read from the file, handy for writing the chunk back out untouched
+    pub fn crc(&self) -> u32 {
+        self.CRC
+    }
 }
 
 /*With this code I have to implement every type of chunk because I am sequentially reading it. However I very well could move the sequential counter forward based
 off chunnk length to avoid reading some chunks which are not needed for decoding, for education I've decided to implement every chunk */
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum Chunk {
-    IDHR(IDHRChunk),
+    IHDR(IHDRChunk),
     PLTE(PLTEChunk),
     IDAT(IDATChunk),
     IEND(IENDChunk),
@@ -645,4 +1512,158 @@ pub enum Chunk {
     tEXt(tEXtChunk),
     tRNS(tRNSChunk),
     zTXt(zTXtChunk),
+    acTL(acTLChunk),
+    fcTL(fcTLChunk),
+    fdAT(fdATChunk),
+    sCAL(sCALChunk),
+    Unknown(UnknownChunk),
+}
+
+impl Chunk {
+    //Type as the four bytes in the file
+    pub fn chunk_type(&self) -> [u8; 4] {
+        match self {
+            Chunk::IHDR(..) => *b"IHDR",
+            Chunk::PLTE(..) => *b"PLTE",
+            Chunk::IDAT(..) => *b"IDAT",
+            Chunk::IEND(..) => *b"IEND",
+            Chunk::tIME(..) => *b"tIME",
+            Chunk::bKGD(..) => *b"bKGD",
+            Chunk::gAMA(..) => *b"gAMA",
+            Chunk::cHRM(..) => *b"cHRM",
+            Chunk::dSIG(..) => *b"dSIG",
+            Chunk::eXIf(..) => *b"eXIf",
+            Chunk::hIST(..) => *b"hIST",
+            Chunk::iCCP(..) => *b"iCCP",
+            Chunk::iTXt(..) => *b"iTXt",
+            Chunk::pHYs(..) => *b"pHYs",
+            Chunk::sBIT(..) => *b"sBIT",
+            Chunk::sPLT(..) => *b"sPLT",
+            Chunk::sRGB(..) => *b"sRGB",
+            Chunk::sTER(..) => *b"sTER",
+            Chunk::tEXt(..) => *b"tEXt",
+            Chunk::tRNS(..) => *b"tRNS",
+            Chunk::zTXt(..) => *b"zTXt",
+            Chunk::acTL(..) => *b"acTL",
+            Chunk::fcTL(..) => *b"fcTL",
+            Chunk::fdAT(..) => *b"fdAT",
+            Chunk::sCAL(..) => *b"sCAL",
+            Chunk::Unknown(chunk) => chunk.chunk_type,
+        }
+    }
+
+    //Length field read from the file, the data only so the length, type and CRC fields add another 12 bytes
+    pub fn length(&self) -> u32 {
+        match self {
+            Chunk::IHDR(chunk) => chunk.length,
+            Chunk::PLTE(chunk) => chunk.length,
+            Chunk::IDAT(chunk) => chunk.length,
+            Chunk::IEND(chunk) => chunk.length,
+            Chunk::tIME(chunk) => chunk.length,
+            Chunk::bKGD(chunk) => chunk.length,
+            Chunk::gAMA(chunk) => chunk.length,
+            Chunk::cHRM(chunk) => chunk.length,
+            Chunk::dSIG(chunk) => chunk.length,
+            Chunk::eXIf(chunk) => chunk.length,
+            Chunk::hIST(chunk) => chunk.length,
+            Chunk::iCCP(chunk) => chunk.length,
+            Chunk::iTXt(chunk) => chunk.length,
+            Chunk::pHYs(chunk) => chunk.length,
+            Chunk::sBIT(chunk) => chunk.length,
+            Chunk::sPLT(chunk) => chunk.length,
+            Chunk::sRGB(chunk) => chunk.length,
+            Chunk::sTER(chunk) => chunk.length,
+            Chunk::tEXt(chunk) => chunk.length,
+            Chunk::tRNS(chunk) => chunk.length,
+            Chunk::zTXt(chunk) => chunk.length,
+            Chunk::acTL(chunk) => chunk.length,
+            Chunk::fcTL(chunk) => chunk.length,
+            Chunk::fdAT(chunk) => chunk.length,
+            Chunk::sCAL(chunk) => chunk.length,
+            Chunk::Unknown(chunk) => chunk.length,
+        }
+    }
+
+    //Stored CRC as read from the file, a plain integer so it compares directly against crc32()
+    pub fn crc(&self) -> u32 {
+        match self {
+            Chunk::IHDR(chunk) => chunk.CRC,
+            Chunk::PLTE(chunk) => chunk.CRC,
+            Chunk::IDAT(chunk) => chunk.CRC,
+            Chunk::IEND(chunk) => chunk.CRC,
+            Chunk::tIME(chunk) => chunk.CRC,
+            Chunk::bKGD(chunk) => chunk.CRC,
+            Chunk::gAMA(chunk) => chunk.CRC,
+            Chunk::cHRM(chunk) => chunk.CRC,
+            Chunk::dSIG(chunk) => chunk.CRC,
+            Chunk::eXIf(chunk) => chunk.CRC,
+            Chunk::hIST(chunk) => chunk.CRC,
+            Chunk::iCCP(chunk) => chunk.CRC,
+            Chunk::iTXt(chunk) => chunk.CRC,
+            Chunk::pHYs(chunk) => chunk.CRC,
+            Chunk::sBIT(chunk) => chunk.CRC,
+            Chunk::sPLT(chunk) => chunk.CRC,
+            Chunk::sRGB(chunk) => chunk.CRC,
+            Chunk::sTER(chunk) => chunk.CRC,
+            Chunk::tEXt(chunk) => chunk.CRC,
+            Chunk::tRNS(chunk) => chunk.CRC,
+            Chunk::zTXt(chunk) => chunk.CRC,
+            Chunk::acTL(chunk) => chunk.CRC,
+            Chunk::fcTL(chunk) => chunk.CRC,
+            Chunk::fdAT(chunk) => chunk.CRC,
+            Chunk::sCAL(chunk) => chunk.CRC,
+            Chunk::Unknown(chunk) => chunk.CRC,
+        }
+    }
+
+    //Data field of whichever chunk this is, see each chunk type's serialize
+    pub fn serialize(&self) -> Vec<u8> {
+        match self {
+            Chunk::IHDR(chunk) => chunk.serialize(),
+            Chunk::PLTE(chunk) => chunk.serialize(),
+            Chunk::IDAT(chunk) => chunk.serialize(),
+            Chunk::IEND(chunk) => chunk.serialize(),
+            Chunk::tIME(chunk) => chunk.serialize(),
+            Chunk::bKGD(chunk) => chunk.serialize(),
+            Chunk::gAMA(chunk) => chunk.serialize(),
+            Chunk::cHRM(chunk) => chunk.serialize(),
+            Chunk::dSIG(chunk) => chunk.serialize(),
+            Chunk::eXIf(chunk) => chunk.serialize(),
+            Chunk::hIST(chunk) => chunk.serialize(),
+            Chunk::iCCP(chunk) => chunk.serialize(),
+            Chunk::iTXt(chunk) => chunk.serialize(),
+            Chunk::pHYs(chunk) => chunk.serialize(),
+            Chunk::sBIT(chunk) => chunk.serialize(),
+            Chunk::sPLT(chunk) => chunk.serialize(),
+            Chunk::sRGB(chunk) => chunk.serialize(),
+            Chunk::sTER(chunk) => chunk.serialize(),
+            Chunk::tEXt(chunk) => chunk.serialize(),
+            Chunk::tRNS(chunk) => chunk.serialize(),
+            Chunk::zTXt(chunk) => chunk.serialize(),
+            Chunk::acTL(chunk) => chunk.serialize(),
+            Chunk::fcTL(chunk) => chunk.serialize(),
+            Chunk::fdAT(chunk) => chunk.serialize(),
+            Chunk::sCAL(chunk) => chunk.serialize(),
+            Chunk::Unknown(chunk) => chunk.serialize(),
+        }
+    }
+
+    pub fn is_critical(&self) -> bool {
+        is_critical_type(&self.chunk_type())
+    }
+
+    pub fn is_safe_to_copy(&self) -> bool {
+        is_safe_to_copy_type(&self.chunk_type())
+    }
+}
+
+/*The case of each letter in a chunk type is a flag (bit 5 set = lowercase). Uppercase first letter means critical: a decoder that
+doesn't know the chunk can't display the image. Lowercase last letter means editors that don't understand it can still copy it
+into a modified file*/
+pub fn is_critical_type(chunk_type: &[u8; 4]) -> bool {
+    chunk_type[0] & 0x20 == 0
+}
+
+pub fn is_safe_to_copy_type(chunk_type: &[u8; 4]) -> bool {
+    chunk_type[3] & 0x20 != 0
 }
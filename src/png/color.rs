@@ -0,0 +1,305 @@
+use crate::png::error::PngError;
+use crate::Png;
+use crate::png::chunks::Chunk;
+
+/*Basic colour management, enough for the common matrix/TRC colour spaces without pulling in a full CMM.
+Samples are linearised with the image's transfer curves, taken to CIE XYZ with a 3x3 matrix (D50 white, the ICC connection space)
+and from there to sRGB. LUT based ICC profiles aren't handled, the samples come back as stored and tagged with the reason*/
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ColorConversion {
+    AlreadySrgb, //sRGB chunk, or no colour information at all which the spec says to treat as sRGB
+    Converted,
+    Unconverted(String),
+}
+
+#[derive(Debug)]
+pub struct SrgbImage {
+    pub width: u32,
+    pub height: u32,
+    pub data: Vec<u8>, //RGBA, alpha is never touched
+    pub conversion: ColorConversion,
+}
+
+type Matrix = [[f64; 3]; 3];
+
+//Linear sRGB <-> XYZ with the D65 white already Bradford adapted to D50
+const SRGB_TO_XYZ_D50: Matrix = [
+    [0.4360747, 0.3850649, 0.1430804],
+    [0.2225045, 0.7168786, 0.0606169],
+    [0.0139322, 0.0971045, 0.7141733],
+];
+const XYZ_D50_TO_SRGB: Matrix = [
+    [3.1338561, -1.6168667, -0.4906146],
+    [-0.9787684, 1.9161415, 0.0334540],
+    [0.0719453, -0.2289914, 1.4052427],
+];
+const BRADFORD: Matrix = [
+    [0.8951, 0.2664, -0.1614],
+    [-0.7502, 1.7135, 0.0367],
+    [0.0389, -0.0685, 1.0296],
+];
+const D50_WHITE: [f64; 3] = [0.9642, 1.0, 0.8249];
+
+//Y = (aX + b)^g + e from d upwards and cX + f below it, every ICC parametric curve type fits this shape
+#[derive(Debug, Clone)]
+enum TransferCurve {
+    Parametric { g: f64, a: f64, b: f64, c: f64, d: f64, e: f64, f: f64 },
+    Table(Vec<f64>),
+}
+
+impl TransferCurve {
+    fn gamma(g: f64) -> Self {
+        TransferCurve::Parametric { g, a: 1.0, b: 0.0, c: 0.0, d: 0.0, e: 0.0, f: 0.0 }
+    }
+
+    fn srgb() -> Self {
+        TransferCurve::Parametric { g: 2.4, a: 1.0 / 1.055, b: 0.055 / 1.055, c: 1.0 / 12.92, d: 0.04045, e: 0.0, f: 0.0 }
+    }
+
+    fn to_linear(&self, x: f64) -> f64 {
+        match self {
+            TransferCurve::Parametric { g, a, b, c, d, e, f } => {
+                if x >= *d {
+                    (a * x + b).max(0.0).powf(*g) + e
+                } else {
+                    c * x + f
+                }
+            }
+            //Table entries are evenly spaced over 0..1 so values between them are interpolated
+            TransferCurve::Table(table) => {
+                let position = x.clamp(0.0, 1.0) * (table.len() - 1) as f64;
+                let index = position.floor() as usize;
+                let next = (index + 1).min(table.len() - 1);
+                table[index] + (table[next] - table[index]) * (position - index as f64)
+            }
+        }
+    }
+}
+
+struct ColorSpace {
+    curves: [TransferCurve; 3],
+    to_xyz_d50: Matrix,
+}
+
+impl<'a> Png<'a> {
+    //RGBA converted to sRGB when the colour space is one this can handle, otherwise the decoded samples as they are
+    pub fn to_srgb8(&self) -> Result<SrgbImage, PngError> {
+        let (width, height, mut data) = self.to_rgba8()?;
+        let conversion = match self.color_space() {
+            Ok(Some(color_space)) => {
+                convert_to_srgb(&mut data, &color_space);
+                ColorConversion::Converted
+            }
+            Ok(None) => ColorConversion::AlreadySrgb,
+            Err(reason) => ColorConversion::Unconverted(reason.to_string()),
+        };
+        Ok(SrgbImage { width, height, data, conversion })
+    }
+
+    //None when the image is sRGB already, an error for colour information that can't be turned into a matrix and curves
+    fn color_space(&self) -> Result<Option<ColorSpace>, PngError> {
+        let mut has_srgb = false;
+        let mut iccp = None;
+        let mut gamma = None;
+        let mut chromaticities = None;
+        for chunk in &self.chunk_list {
+            match chunk {
+                Chunk::sRGB(..) => has_srgb = true,
+                Chunk::iCCP(iCCP_chunk) => iccp = Some(iCCP_chunk),
+                Chunk::gAMA(gAMA_chunk) => gamma = Some(gAMA_chunk.gamma_value()),
+                Chunk::cHRM(cHRM_chunk) => chromaticities = Some(cHRM_chunk),
+                _ => {}
+            }
+        }
+
+        //sRGB takes priority over the others, and iCCP over gAMA/cHRM which are only there for decoders without ICC support
+        if has_srgb {
+            return Ok(None);
+        }
+        if let Some(iCCP_chunk) = iccp {
+            return parse_icc_profile(&iCCP_chunk.icc_profile(self.options().max_decompressed_metadata)?).map(Some);
+        }
+        if gamma.is_none() && chromaticities.is_none() {
+            return Ok(None);
+        }
+
+        //gAMA is the encoding exponent, so linear = sample^(1 / gamma)
+        let curve = match gamma {
+            Some(gamma) => TransferCurve::gamma(1.0 / gamma),
+            None => TransferCurve::srgb(),
+        };
+        let to_xyz_d50 = match chromaticities {
+            Some(cHRM_chunk) => {
+                matrix_from_chromaticities(cHRM_chunk.white_point(), cHRM_chunk.red(), cHRM_chunk.green(), cHRM_chunk.blue()).ok_or_else(|| PngError::MalformedChunk("cHRM chromaticities are degenerate".to_string()))?
+            }
+            None => SRGB_TO_XYZ_D50,
+        };
+        Ok(Some(ColorSpace { curves: [curve.clone(), curve.clone(), curve], to_xyz_d50 }))
+    }
+}
+
+fn convert_to_srgb(rgba: &mut [u8], color_space: &ColorSpace) {
+    let matrix = multiply_matrices(&XYZ_D50_TO_SRGB, &color_space.to_xyz_d50);
+    //Only 256 possible inputs per channel so the curves are worked out once up front
+    let linear: Vec<[f64; 256]> = color_space.curves.iter()
+        .map(|curve| std::array::from_fn(|value| curve.to_linear(value as f64 / 255.0)))
+        .collect();
+
+    for pixel in rgba.chunks_exact_mut(4) {
+        let source = [linear[0][pixel[0] as usize], linear[1][pixel[1] as usize], linear[2][pixel[2] as usize]];
+        let srgb = multiply_vector(&matrix, source);
+        for (sample, value) in pixel[..3].iter_mut().zip(srgb) {
+            *sample = (encode_srgb(value.clamp(0.0, 1.0)) * 255.0).round() as u8;
+        }
+    }
+}
+
+fn encode_srgb(linear: f64) -> f64 {
+    if linear <= 0.0031308 {
+        linear * 12.92
+    } else {
+        1.055 * linear.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/*RGB -> XYZ from xy chromaticities: each primary's XYZ at Y = 1 is scaled so the three add up to the white point,
+then the result is Bradford adapted from that white point to D50*/
+fn matrix_from_chromaticities(white: (f64, f64), red: (f64, f64), green: (f64, f64), blue: (f64, f64)) -> Option<Matrix> {
+    let to_xyz = |(x, y): (f64, f64)| if y == 0.0 { None } else { Some([x / y, 1.0, (1.0 - x - y) / y]) };
+    let white = to_xyz(white)?;
+    let primaries = [to_xyz(red)?, to_xyz(green)?, to_xyz(blue)?];
+    let primaries: Matrix = std::array::from_fn(|row| std::array::from_fn(|column| primaries[column][row]));
+
+    let scale = multiply_vector(&invert(&primaries)?, white);
+    let rgb_to_xyz: Matrix = std::array::from_fn(|row| std::array::from_fn(|column| primaries[row][column] * scale[column]));
+
+    let source_cone = multiply_vector(&BRADFORD, white);
+    let destination_cone = multiply_vector(&BRADFORD, D50_WHITE);
+    let mut cone_scale = [[0.0; 3]; 3];
+    for i in 0..3 {
+        cone_scale[i][i] = destination_cone[i] / source_cone[i];
+    }
+    let adaptation = multiply_matrices(&invert(&BRADFORD)?, &multiply_matrices(&cone_scale, &BRADFORD));
+    Some(multiply_matrices(&adaptation, &rgb_to_xyz))
+}
+
+fn multiply_vector(matrix: &Matrix, vector: [f64; 3]) -> [f64; 3] {
+    std::array::from_fn(|row| (0..3).map(|i| matrix[row][i] * vector[i]).sum())
+}
+
+fn multiply_matrices(left: &Matrix, right: &Matrix) -> Matrix {
+    std::array::from_fn(|row| std::array::from_fn(|column| (0..3).map(|i| left[row][i] * right[i][column]).sum()))
+}
+
+fn invert(m: &Matrix) -> Option<Matrix> {
+    let cofactor = |row: usize, column: usize| {
+        let (r1, r2) = ((row + 1) % 3, (row + 2) % 3);
+        let (c1, c2) = ((column + 1) % 3, (column + 2) % 3);
+        m[r1][c1] * m[r2][c2] - m[r1][c2] * m[r2][c1]
+    };
+    let determinant: f64 = (0..3).map(|column| m[0][column] * cofactor(0, column)).sum();
+    if determinant.abs() < 1e-12 {
+        return None;
+    }
+    Some(std::array::from_fn(|row| std::array::from_fn(|column| cofactor(column, row) / determinant)))
+}
+
+/*ICC profile layout (everything big endian):
+- 128 byte header, colour space signature at 16 and connection space at 20
+- tag count (4 bytes) then 12 bytes per tag: signature, offset from the start of the profile, size
+Matrix/TRC profiles have rXYZ/gXYZ/bXYZ (the matrix columns) and rTRC/gTRC/bTRC (the curves)*/
+fn parse_icc_profile(profile: &[u8]) -> Result<ColorSpace, PngError> {
+    if profile.len() < 132 {
+        return Err(PngError::MalformedChunk("ICC profile is too short".to_string()));
+    }
+    if &profile[16..20] != b"RGB " {
+        return Err(PngError::MalformedChunk("ICC profile is not an RGB profile".to_string()));
+    }
+    if &profile[20..24] != b"XYZ " {
+        return Err(PngError::MalformedChunk("ICC profile connection space is not XYZ".to_string()));
+    }
+
+    let tag_count = read_u32(profile, 128)? as usize;
+    let tag = |signature: &[u8; 4]| -> Result<&[u8], PngError> {
+        for index in 0..tag_count {
+            let entry = 132 + index * 12;
+            if profile.get(entry..entry + 4).ok_or_else(|| PngError::MalformedChunk("ICC tag table is truncated".to_string()))? == signature {
+                let offset = read_u32(profile, entry + 4)? as usize;
+                let size = read_u32(profile, entry + 8)? as usize;
+                return profile.get(offset..offset.saturating_add(size)).ok_or_else(|| PngError::MalformedChunk("ICC tag runs past the end of the profile".to_string()));
+            }
+        }
+        Err(PngError::MalformedChunk(format!("ICC profile has no {} tag, only matrix/TRC profiles are supported", String::from_utf8_lossy(signature))))
+    };
+
+    let mut to_xyz_d50 = [[0.0; 3]; 3];
+    for (column, signature) in [b"rXYZ", b"gXYZ", b"bXYZ"].into_iter().enumerate() {
+        let data = tag(signature)?;
+        if data.get(..4) != Some(b"XYZ ".as_slice()) {
+            return Err(PngError::MalformedChunk("ICC colorant tag is not an XYZ type".to_string()));
+        }
+        for (row, values) in to_xyz_d50.iter_mut().enumerate() {
+            values[column] = read_s15_fixed16(data, 8 + row * 4)?;
+        }
+    }
+    let curves = [parse_curve(tag(b"rTRC")?)?, parse_curve(tag(b"gTRC")?)?, parse_curve(tag(b"bTRC")?)?];
+    Ok(ColorSpace { curves, to_xyz_d50 })
+}
+
+//curv is a gamma (1 entry as u8Fixed8) or a table of u16s, para is one of the five parametric function types
+fn parse_curve(data: &[u8]) -> Result<TransferCurve, PngError> {
+    match data.get(..4) {
+        Some(b"curv") => {
+            let count = read_u32(data, 8)? as usize;
+            match count {
+                0 => Ok(TransferCurve::gamma(1.0)),
+                1 => Ok(TransferCurve::gamma(read_u16(data, 12)? as f64 / 256.0)),
+                _ => {
+                    let mut table = Vec::with_capacity(count.min(data.len() / 2));
+                    for index in 0..count {
+                        table.push(read_u16(data, 12 + index * 2)? as f64 / 65535.0);
+                    }
+                    Ok(TransferCurve::Table(table))
+                }
+            }
+        }
+        Some(b"para") => {
+            let function_type = read_u16(data, 8)?;
+            let parameter_count = match function_type {
+                0 => 1,
+                1 => 3,
+                2 => 4,
+                3 => 5,
+                4 => 7,
+                _ => return Err(PngError::MalformedChunk(format!("Unknown ICC parametric curve type {}", function_type))),
+            };
+            let mut parameters = [0.0; 7];
+            for (index, parameter) in parameters.iter_mut().enumerate().take(parameter_count) {
+                *parameter = read_s15_fixed16(data, 12 + index * 4)?;
+            }
+            let [g, a, b, c, d, e, f] = parameters;
+            Ok(match function_type {
+                0 => TransferCurve::gamma(g),
+                1 => TransferCurve::Parametric { g, a, b, c: 0.0, d: -b / a, e: 0.0, f: 0.0 },
+                2 => TransferCurve::Parametric { g, a, b, c: 0.0, d: -b / a, e: c, f: c },
+                _ => TransferCurve::Parametric { g, a, b, c, d, e, f },
+            })
+        }
+        _ => Err(PngError::MalformedChunk("Unsupported ICC curve type".to_string())),
+    }
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Result<u32, PngError> {
+    let bytes = data.get(offset..offset + 4).ok_or_else(|| PngError::MalformedChunk("ICC profile is truncated".to_string()))?;
+    Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Result<u16, PngError> {
+    let bytes = data.get(offset..offset + 2).ok_or_else(|| PngError::MalformedChunk("ICC profile is truncated".to_string()))?;
+    Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+}
+
+fn read_s15_fixed16(data: &[u8], offset: usize) -> Result<f64, PngError> {
+    Ok(read_u32(data, offset)? as i32 as f64 / 65536.0)
+}
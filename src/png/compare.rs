@@ -0,0 +1,36 @@
+use crate::png::error::PngError;
+use crate::Png;
+
+//Result of comparing two images after decoding both to RGBA, pixel counts are only filled in when the dimensions match
+#[derive(Debug)]
+pub struct ComparisonReport {
+    pub dimensions_match: bool,
+    pub differing_pixels: u64,
+    pub max_channel_difference: u8,
+}
+
+impl ComparisonReport {
+    pub fn identical(&self) -> bool {
+        self.dimensions_match && self.differing_pixels == 0
+    }
+}
+
+//Compares decoded pixels rather than file bytes so the same image saved with different filters/compression counts as equal
+pub fn compare(a: &Png, b: &Png) -> Result<ComparisonReport, PngError> {
+    let (a_width, a_height, a_pixels) = a.to_rgba8()?;
+    let (b_width, b_height, b_pixels) = b.to_rgba8()?;
+    if (a_width, a_height) != (b_width, b_height) {
+        return Ok(ComparisonReport { dimensions_match: false, differing_pixels: 0, max_channel_difference: 0 });
+    }
+
+    let mut differing_pixels = 0;
+    let mut max_channel_difference = 0;
+    for (a_pixel, b_pixel) in a_pixels.chunks(4).zip(b_pixels.chunks(4)) {
+        let difference = a_pixel.iter().zip(b_pixel).map(|(a, b)| a.abs_diff(*b)).max().unwrap_or(0);
+        if difference > 0 {
+            differing_pixels += 1;
+            max_channel_difference = max_channel_difference.max(difference);
+        }
+    }
+    Ok(ComparisonReport { dimensions_match: true, differing_pixels, max_channel_difference })
+}
@@ -0,0 +1,60 @@
+/*CRC-32 as used by PNG (ISO 3309 / ITU-T V.42), polynomial 0xEDB88320 in its reflected form.
+The table is built at compile time so every byte only needs a single lookup*/
+const CRC_TABLE: [u32; 256] = make_crc_table();
+
+const fn make_crc_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut n = 0;
+    while n < 256 {
+        let mut c = n as u32;
+        let mut k = 0;
+        while k < 8 {
+            if c & 1 == 1 {
+                c = 0xEDB88320 ^ (c >> 1);
+            } else {
+                c >>= 1;
+            }
+            k += 1;
+        }
+        table[n] = c;
+        n += 1;
+    }
+    table
+}
+
+//Register starts as all 1's and is inverted at the end, the chunk CRC covers the type bytes and the data bytes
+pub fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = Crc32::new();
+    crc.update(bytes);
+    crc.finalize()
+}
+
+//Running CRC-32 for data that arrives in pieces, feeding the pieces in order gives the same value as crc32 over all of them
+#[derive(Debug, Clone)]
+pub struct Crc32 {
+    register: u32,
+}
+
+impl Crc32 {
+    pub fn new() -> Self {
+        Self { register: 0xFFFFFFFF }
+    }
+
+    pub fn update(&mut self, bytes: &[u8]) {
+        let mut c = self.register;
+        for &byte in bytes {
+            c = CRC_TABLE[((c ^ byte as u32) & 0xFF) as usize] ^ (c >> 8);
+        }
+        self.register = c;
+    }
+
+    pub fn finalize(self) -> u32 {
+        self.register ^ 0xFFFFFFFF
+    }
+}
+
+impl Default for Crc32 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,681 @@
+use crate::png::error::PngError;
+use crate::Png;
+use crate::png::chunks::{Chunk, Color, ColorType, IHDRChunk, InterlaceMethod, PaletteEntry, PLTEChunk, TransparencyInfo, bKGDChunk, tRNSChunk};
+use crate::png::inflate::{zlib_decompress, ZlibReader};
+
+/*Decoding goes concatenated_idat() -> inflate() -> raw_scanlines() -> unfilter_scanlines() -> deinterlace() -> expand_to_rgba(),
+each stage is public and takes the previous one's output so the intermediate data can be inspected.
+The Raster sits after defiltering/deinterlacing and keeps the samples at their original bit depth so nothing is lost
+before a caller picks an output format*/
+
+//Adam7 passes as (x start, y start, x step, y step)
+const ADAM7_PASSES: [(usize, usize, usize, usize); 7] = [
+    (0, 0, 8, 8),
+    (4, 0, 8, 8),
+    (0, 4, 4, 8),
+    (2, 0, 4, 4),
+    (0, 2, 2, 4),
+    (1, 0, 2, 2),
+    (0, 1, 1, 2),
+];
+
+//Rows are packed exactly like a PNG scanline without its filter byte, so sub byte pixels stay packed MSB first
+#[derive(Debug)]
+pub struct Raster {
+    pub width: u32,
+    pub height: u32,
+    pub bit_depth: u8,
+    pub color_type: ColorType,
+    pub stride: usize,
+    pub data: Vec<u8>,
+}
+
+impl Raster {
+    pub fn row(&self, y: u32) -> &[u8] {
+        let start = y as usize * self.stride;
+        &self.data[start..start + self.stride]
+    }
+
+    //Value of one channel of one pixel at the image's own bit depth (0 to 2^bit_depth - 1)
+    pub fn sample(&self, x: u32, y: u32, channel: usize) -> u16 {
+        let index = x as usize * channels(self.color_type) + channel;
+        read_sample(self.row(y), index, self.bit_depth)
+    }
+}
+
+/*Sub image of an Adam7 pass (or the whole image when it isn't interlaced) with its pixel positions in the full image.
+data holds filter byte + scanline for each row until the unfilter stage, and just the packed rows after it*/
+#[derive(Debug)]
+pub struct ImagePass {
+    pub x_start: usize,
+    pub y_start: usize,
+    pub x_step: usize,
+    pub y_step: usize,
+    pub width: usize,
+    pub height: usize,
+    pub stride: usize,
+    pub data: Vec<u8>,
+}
+
+pub fn channels(color_type: ColorType) -> usize {
+    match color_type {
+        ColorType::Grayscale | ColorType::Indexed => 1,
+        ColorType::GrayscaleAlpha => 2,
+        ColorType::RGB => 3,
+        ColorType::RGBA => 4,
+    }
+}
+
+fn read_sample(row: &[u8], index: usize, bit_depth: u8) -> u16 {
+    match bit_depth {
+        16 => u16::from_be_bytes([row[index * 2], row[index * 2 + 1]]),
+        8 => row[index] as u16,
+        _ => {
+            let bit = index * bit_depth as usize;
+            let shift = 8 - bit_depth as usize - bit % 8;
+            ((row[bit / 8] >> shift) & ((1u8 << bit_depth) - 1)) as u16
+        }
+    }
+}
+
+/*One byte per sample (0 to 2^bit_depth - 1) for a packed 1, 2, 4 or 8 bit scanline, most significant bits first. Only the first width
+samples are taken so the padding bits at the end of the last byte are ignored. Single channel only (gray or palette indices)*/
+pub fn unpack_bits(scanline: &[u8], bit_depth: u8, width: u32) -> Vec<u8> {
+    (0..width as usize).map(|index| read_sample(scanline, index, bit_depth) as u8).collect()
+}
+
+/*Sub byte samples use the spec's bit replication, the sample's bits are repeated until the byte is full so the maximum maps to 255
+(a 2 bit value v becomes v * 85, a 1 bit 1 becomes 255), left shifting alone would turn a 2 bit 3 into 192. 16 bit samples keep their high byte*/
+pub fn scale_to_u8(value: u16, bit_depth: u8) -> u8 {
+    match bit_depth {
+        16 => (value >> 8) as u8,
+        8 => value as u8,
+        _ => {
+            let mut scaled = 0u16;
+            let mut filled = 0;
+            while filled < 8 {
+                scaled = (scaled << bit_depth) | value;
+                filled += bit_depth;
+            }
+            scaled as u8
+        }
+    }
+}
+
+//Sizes are worked out in u64 and checked so a huge image fails cleanly on 32 bit targets instead of wrapping to a small allocation
+fn checked_size(size: Option<u64>) -> Result<usize, PngError> {
+    size.and_then(|size| usize::try_from(size).ok())
+        .ok_or_else(|| PngError::LimitExceeded("image too large for platform".to_string()))
+}
+
+//Bytes in one packed row of pixels, not counting the filter byte
+pub fn row_bytes(width: usize, bits_per_pixel: usize) -> Result<usize, PngError> {
+    checked_size((width as u64).checked_mul(bits_per_pixel as u64).map(|bits| bits.div_ceil(8)))
+}
+
+pub fn buffer_size(row_bytes: usize, rows: usize) -> Result<usize, PngError> {
+    checked_size((row_bytes as u64).checked_mul(rows as u64))
+}
+
+pub fn pixel_buffer_size(width: u32, height: u32, bytes_per_pixel: usize) -> Result<usize, PngError> {
+    checked_size((width as u64).checked_mul(height as u64).and_then(|pixels| pixels.checked_mul(bytes_per_pixel as u64)))
+}
+
+impl<'a> Png<'a> {
+    pub(crate) fn header(&self) -> Result<&IHDRChunk, PngError> {
+        self.find_ihdr().ok_or_else(|| PngError::MissingChunk("IHDR".to_string()))
+    }
+
+    pub(crate) fn palette(&self) -> Option<&PLTEChunk> {
+        self.chunk_list.iter().find_map(|p| match p {
+            Chunk::PLTE(PLTE_chunk) => Some(PLTE_chunk),
+            _ => None,
+        })
+    }
+
+    pub(crate) fn transparency(&self) -> Option<&tRNSChunk> {
+        self.chunk_list.iter().find_map(|p| match p {
+            Chunk::tRNS(tRNS_chunk) => Some(tRNS_chunk),
+            _ => None,
+        })
+    }
+
+    fn background(&self) -> Option<&bKGDChunk> {
+        self.chunk_list.iter().find_map(|p| match p {
+            Chunk::bKGD(bKGD_chunk) => Some(bKGD_chunk),
+            _ => None,
+        })
+    }
+
+    /*bKGD as RGB without any scaling, gray and RGB backgrounds are at the image's bit depth and indexed ones take
+    the 8 bit palette entry. None without bKGD or when the index is past the end of PLTE*/
+    pub fn background_rgb(&self) -> Option<[u16; 3]> {
+        match self.background()?.color() {
+            Color::Gray(gray) => Some([*gray; 3]),
+            Color::RGB(red, green, blue) => Some([*red, *green, *blue]),
+            Color::PaletteIndex(index) => self.palette()?.entries().get(*index as usize).map(|entry| entry.rgb().map(u16::from)),
+        }
+    }
+
+    //bKGD resolved to 8 bit RGB, indexed backgrounds are looked up in the palette
+    fn background_rgb8(&self, bit_depth: u8) -> Option<[u8; 3]> {
+        match self.background()?.color() {
+            Color::Gray(gray) => {
+                let gray = scale_to_u8(*gray, bit_depth);
+                Some([gray, gray, gray])
+            }
+            Color::RGB(red, green, blue) => Some([scale_to_u8(*red, bit_depth), scale_to_u8(*green, bit_depth), scale_to_u8(*blue, bit_depth)]),
+            Color::PaletteIndex(index) => self.palette()?.entries().get(*index as usize).map(PaletteEntry::rgb),
+        }
+    }
+
+    //Stage 1: encoders split the zlib stream across IDAT chunks wherever they like so the data has to be joined in file order
+    pub fn concatenated_idat(&self) -> Vec<u8> {
+        let mut compressed = Vec::new();
+        for IDAT_chunk in self.idat_chunks() {
+            compressed.extend_from_slice(IDAT_chunk.data());
+        }
+        compressed
+    }
+
+    /*Stage 2: zlib inflate, the Adler-32 is checked unless the options turn it off.
+    max_output is the size the raster should come out as, anything past it can only be garbage or a zip bomb*/
+    pub fn inflate(&self, compressed: &[u8], max_output: usize) -> Result<Vec<u8>, PngError> {
+        zlib_decompress(compressed, !self.options().ignore_adler, Some(max_output))
+    }
+
+    //Files without IDAT are fine for metadata, only the pixel producing methods fail and with a message that isn't a zlib error
+    pub fn decompress_image_data(&self) -> Result<Vec<u8>, PngError> {
+        if self.idat_chunks().next().is_none() {
+            return Err(PngError::NoImageData);
+        }
+        self.inflate(&self.concatenated_idat(), self.uncompressed_raster_size()?)
+    }
+
+    //Stage 3: splits the inflated data into each pass's filtered scanlines, a non interlaced image is one pass covering everything
+    pub fn raw_scanlines(&self, inflated: Vec<u8>) -> Result<Vec<ImagePass>, PngError> {
+        let mut passes = self.pass_layout()?;
+        let mut offset = 0;
+        for pass in &mut passes {
+            let size = buffer_size(pass.stride + 1, pass.height)?;
+            pass.data = inflated.get(offset..offset + size).ok_or_else(|| PngError::MalformedChunk("Decompressed image data is too short".to_string()))?.to_vec();
+            offset += size;
+        }
+        Ok(passes)
+    }
+
+    //Every pass the image data is made of with its data left empty, passes with no pixels (tiny images) have no scanlines at all, not even filter bytes
+    fn pass_layout(&self) -> Result<Vec<ImagePass>, PngError> {
+        let header = self.header()?;
+        self.pass_layout_for(header.width(), header.height())
+    }
+
+    //Same as pass_layout for an area of a different size (APNG frames), the pixel format and interlacing still come from IHDR
+    pub(crate) fn pass_layout_for(&self, image_width: u32, image_height: u32) -> Result<Vec<ImagePass>, PngError> {
+        let header = self.header()?;
+        let pass_origins: &[(usize, usize, usize, usize)] = match header.interlace_method() {
+            InterlaceMethod::None => &[(0, 0, 1, 1)],
+            InterlaceMethod::Adam7 => &ADAM7_PASSES,
+        };
+
+        let mut passes = Vec::new();
+        for &(x_start, y_start, x_step, y_step) in pass_origins {
+            let width = (image_width as usize).saturating_sub(x_start).div_ceil(x_step);
+            let height = (image_height as usize).saturating_sub(y_start).div_ceil(y_step);
+            if width == 0 || height == 0 {
+                continue;
+            }
+            let stride = header.row_bytes_for(width)?;
+            passes.push(ImagePass { x_start, y_start, x_step, y_step, width, height, stride, data: Vec::new() });
+        }
+        Ok(passes)
+    }
+
+    /*Size the inflated IDAT data should be, worked out from IHDR alone so buffers can be planned before decoding.
+    Each scanline is its packed bytes plus a filter byte, and an interlaced image is the sum of its seven Adam7 passes
+    which comes out larger than height * (1 + stride) because every pass row has its own filter byte and partial byte*/
+    pub fn uncompressed_raster_size(&self) -> Result<usize, PngError> {
+        let header = self.header()?;
+        self.raster_size_for(header.width(), header.height())
+    }
+
+    //Same as uncompressed_raster_size for an area of a different size (APNG frames)
+    pub(crate) fn raster_size_for(&self, image_width: u32, image_height: u32) -> Result<usize, PngError> {
+        let mut total = 0usize;
+        for pass in self.pass_layout_for(image_width, image_height)? {
+            total = total.checked_add(buffer_size(pass.stride + 1, pass.height)?).ok_or_else(|| PngError::LimitExceeded("image too large for platform".to_string()))?;
+        }
+        Ok(total)
+    }
+
+    //Stage 4: reverses the filter on every scanline of every pass, the filter bytes are dropped
+    pub fn unfilter_scanlines(&self, passes: Vec<ImagePass>) -> Result<Vec<ImagePass>, PngError> {
+        self.unfilter_scanlines_with_progress(passes, &mut |_, _| {})
+    }
+
+    /*on_progress gets (rows done, rows total) after every defiltered scanline, for an interlaced image the total counts every pass's rows.
+    The cancellation flag is checked at the same points so a long decode can be both watched and stopped*/
+    pub fn unfilter_scanlines_with_progress(&self, passes: Vec<ImagePass>, on_progress: &mut dyn FnMut(u32, u32)) -> Result<Vec<ImagePass>, PngError> {
+        let header = self.header()?;
+        let bytes_per_pixel = header.bytes_per_pixel();
+        let rows_total = u32::try_from(passes.iter().map(|pass| pass.height as u64).sum::<u64>()).unwrap_or(u32::MAX);
+        let mut rows_done = 0u32;
+        passes.into_iter()
+            .map(|pass| {
+                let data = unfilter_rows(&pass.data, pass.stride, pass.height, bytes_per_pixel, || {
+                    rows_done = rows_done.saturating_add(1);
+                    on_progress(rows_done, rows_total);
+                    if self.options().is_cancelled() {
+                        return Err(PngError::Cancelled);
+                    }
+                    Ok(())
+                })?;
+                Ok(ImagePass { data, ..pass })
+            })
+            .collect()
+    }
+
+    /*Defilters one block of width x height scanlines (filter byte + row each) on its own, for data that isn't split into passes.
+    bytes_per_pixel is the filter's byte distance (1 for sub byte pixels) and has to agree with IHDR's pixel format, which also gives the row length*/
+    pub fn defilter(&self, decompressed: &[u8], width: u32, height: u32, bytes_per_pixel: usize) -> Result<Vec<u8>, PngError> {
+        let header = self.header()?;
+        if bytes_per_pixel != header.bytes_per_pixel() {
+            return Err(PngError::InvalidArgument(format!("{} bytes per pixel does not match the {} bits per pixel in IHDR", bytes_per_pixel, header.bits_per_pixel())));
+        }
+        let stride = header.row_bytes_for(width as usize)?;
+        unfilter_rows(decompressed, stride, height as usize, bytes_per_pixel, || Ok(()))
+    }
+
+    //Stage 5: places each pass's pixels into the full image, a single pass covering the whole image is passed through untouched
+    pub fn deinterlace(&self, mut passes: Vec<ImagePass>) -> Result<Raster, PngError> {
+        let header = self.header()?;
+        let width = header.width() as usize;
+        let height = header.height() as usize;
+        let bits_per_pixel = header.bits_per_pixel();
+        let stride = header.scanline_bytes()?;
+        let size = buffer_size(stride, height)?;
+
+        let data = if passes.len() == 1 && passes[0].x_step == 1 && passes[0].y_step == 1 {
+            passes.remove(0).data
+        } else {
+            let mut output = vec![0u8; size];
+            for pass in &passes {
+                for pass_y in 0..pass.height {
+                    let source = &pass.data[pass_y * pass.stride..(pass_y + 1) * pass.stride];
+                    let y = pass.y_start + pass_y * pass.y_step;
+                    let destination = &mut output[y * stride..(y + 1) * stride];
+                    for pass_x in 0..pass.width {
+                        copy_pixel(source, pass_x, destination, pass.x_start + pass_x * pass.x_step, bits_per_pixel);
+                    }
+                }
+            }
+            output
+        };
+        if data.len() != size {
+            return Err(PngError::InvalidArgument("Image passes do not match the image size".to_string()));
+        }
+
+        Ok(Raster {
+            width: header.width(),
+            height: header.height(),
+            bit_depth: header.bit_depth(),
+            color_type: header.color_type(),
+            stride,
+            data,
+        })
+    }
+
+    //Stage 6: every pixel expanded to 8 bit RGBA
+    pub fn expand_to_rgba(&self, raster: &Raster) -> Result<Vec<u8>, PngError> {
+        let palette = self.palette().map(PLTEChunk::entries);
+        let transparency = self.transparency().map(tRNSChunk::transparency);
+
+        let mut rgba = Vec::with_capacity(pixel_buffer_size(raster.width, raster.height, 4)?);
+        for y in 0..raster.height {
+            for x in 0..raster.width {
+                rgba.extend_from_slice(&pixel_rgba8(raster, x, y, palette, transparency)?);
+            }
+        }
+        Ok(rgba)
+    }
+
+    pub fn raster(&self) -> Result<Raster, PngError> {
+        self.raster_with_progress(&mut |_, _| {})
+    }
+
+    pub fn raster_with_progress(&self, on_progress: &mut dyn FnMut(u32, u32)) -> Result<Raster, PngError> {
+        let passes = self.raw_scanlines(self.decompress_image_data()?)?;
+        if self.options().is_cancelled() {
+            return Err(PngError::Cancelled);
+        }
+        self.deinterlace(self.unfilter_scanlines_with_progress(passes, on_progress)?)
+    }
+
+    //Defiltered rows from top to bottom without building the whole raster, see Scanlines
+    pub fn scanlines(&self) -> Result<Scanlines, PngError> {
+        let header = self.header()?;
+        let rows = header.height() as usize;
+        let stride = header.scanline_bytes()?;
+
+        let source = match header.interlace_method() {
+            InterlaceMethod::None => {
+                if self.idat_chunks().next().is_none() {
+                    return Err(PngError::NoImageData);
+                }
+                ScanlineSource::Filtered(ZlibReader::new(self.concatenated_idat(), Some(self.uncompressed_raster_size()?))?)
+            }
+            InterlaceMethod::Adam7 => {
+                let decompressed = self.decompress_image_data()?;
+                ScanlineSource::Deinterlaced(self.deinterlace(self.unfilter_scanlines(self.raw_scanlines(decompressed)?)?)?.data)
+            }
+        };
+
+        Ok(Scanlines {
+            source,
+            check_adler: !self.options().ignore_adler,
+            stride,
+            bytes_per_pixel: header.bytes_per_pixel(),
+            rows,
+            next_row: 0,
+            current: vec![0; stride],
+            previous: vec![0; stride],
+        })
+    }
+
+    //8 bit alpha plane for masking, taken from the alpha channel, from tRNS for indexed/keyed images, or 255 for opaque images
+    pub fn alpha_channel(&self) -> Result<(u32, u32, Vec<u8>), PngError> {
+        let header = self.header()?;
+        let transparency = self.transparency().map(tRNSChunk::transparency);
+        let opaque = matches!(header.color_type(), ColorType::Grayscale | ColorType::RGB) && transparency.is_none();
+        //The shortcut never looks at the pixels, a file with no image data still has to fail like the other pixel methods
+        if opaque && self.idat_chunks().next().is_none() {
+            return Err(PngError::NoImageData);
+        }
+        if opaque {
+            let pixel_count = pixel_buffer_size(header.width(), header.height(), 1)?;
+            return Ok((header.width(), header.height(), vec![255; pixel_count]));
+        }
+
+        let raster = self.raster()?;
+        let mut alpha = Vec::with_capacity(pixel_buffer_size(raster.width, raster.height, 1)?);
+        for y in 0..raster.height {
+            for x in 0..raster.width {
+                alpha.push(pixel_alpha(&raster, x, y, transparency));
+            }
+        }
+        Ok((raster.width, raster.height, alpha))
+    }
+
+    /*Tightly packed 8 bit RGBA whatever the source colour type. Indexed pixels take their RGB from PLTE and their alpha
+    from tRNS, palette entries past the end of tRNS are opaque. Gray and RGB pixels matching the tRNS key become transparent,
+    16 bit samples keep their high byte and low bit depths are scaled up to the full 0-255 range*/
+    pub fn to_rgba8(&self) -> Result<(u32, u32, Vec<u8>), PngError> {
+        self.to_rgba8_with_progress(&mut |_, _| {})
+    }
+
+    //to_rgba8 reporting progress per scanline, see unfilter_scanlines_with_progress
+    pub fn to_rgba8_with_progress(&self, on_progress: &mut dyn FnMut(u32, u32)) -> Result<(u32, u32, Vec<u8>), PngError> {
+        let raster = self.raster_with_progress(on_progress)?;
+        let rgba = self.expand_to_rgba(&raster)?;
+        Ok((raster.width, raster.height, rgba))
+    }
+
+    //Same pixels as to_rgba8 but each row starts row_stride bytes after the last (GPU uploads often want 256 byte aligned rows), the padding is left as zeroes
+    pub fn to_rgba8_with_stride(&self, row_stride: usize) -> Result<(u32, u32, Vec<u8>), PngError> {
+        let raster = self.raster()?;
+        let row_length = row_bytes(raster.width as usize, 32)?;
+        if row_stride < row_length {
+            return Err(PngError::InvalidArgument(format!("Row stride {} is smaller than a row of {} bytes", row_stride, row_length)));
+        }
+
+        let rgba = self.expand_to_rgba(&raster)?;
+        let mut output = vec![0u8; buffer_size(row_stride, raster.height as usize)?];
+        if row_length > 0 {
+            for (source, destination) in rgba.chunks(row_length).zip(output.chunks_mut(row_stride)) {
+                destination[..row_length].copy_from_slice(source);
+            }
+        }
+        Ok((raster.width, raster.height, output))
+    }
+
+    /*One RGBA slice per image row so pixels can be read as rows[y][x * 4..x * 4 + 4].
+    The decoded image is kept after the first call so later calls don't decode again, it won't see changes made to chunk_list afterwards*/
+    pub fn rows(&self) -> Result<Vec<&[u8]>, PngError> {
+        let (width, _, rgba) = match self.rgba_cache().get() {
+            Some(cached) => cached,
+            None => {
+                let decoded = self.to_rgba8()?;
+                self.rgba_cache().get_or_init(|| decoded)
+            }
+        };
+        let row_length = *width as usize * 4;
+        if row_length == 0 {
+            return Ok(Vec::new());
+        }
+        Ok(rgba.chunks(row_length).collect())
+    }
+
+    /*3 bytes per pixel in one pass over the raster. With composite set transparent pixels are blended over bKGD (or white),
+    otherwise alpha is just dropped and fully transparent pixels show whatever colour they were stored with*/
+    pub fn to_rgb8(&self, composite: bool) -> Result<(u32, u32, Vec<u8>), PngError> {
+        if composite {
+            return self.to_rgb8_on_background();
+        }
+
+        let raster = self.raster()?;
+        let palette = self.palette().map(PLTEChunk::entries);
+        let transparency = self.transparency().map(tRNSChunk::transparency);
+        let mut rgb = Vec::with_capacity(pixel_buffer_size(raster.width, raster.height, 3)?);
+        for y in 0..raster.height {
+            for x in 0..raster.width {
+                rgb.extend_from_slice(&pixel_rgba8(&raster, x, y, palette, transparency)?[..3]);
+            }
+        }
+        Ok((raster.width, raster.height, rgb))
+    }
+
+    //Opaque RGB of the image composited over its bKGD colour, or over white when there's no bKGD
+    pub fn to_rgb8_on_background(&self) -> Result<(u32, u32, Vec<u8>), PngError> {
+        self.composite_on_background([255, 255, 255], false)
+    }
+
+    //RGBA that can be drawn without caring about alpha, every pixel is composited over bKGD (or the fallback) and left at alpha 255
+    pub fn to_opaque_rgba8(&self, fallback: [u8; 3]) -> Result<(u32, u32, Vec<u8>), PngError> {
+        self.composite_on_background(fallback, true)
+    }
+
+    fn composite_on_background(&self, fallback: [u8; 3], with_alpha: bool) -> Result<(u32, u32, Vec<u8>), PngError> {
+        let raster = self.raster()?;
+        let background = self.background_rgb8(raster.bit_depth).unwrap_or(fallback);
+        let palette = self.palette().map(PLTEChunk::entries);
+        let transparency = self.transparency().map(tRNSChunk::transparency);
+
+        let bytes_per_pixel = if with_alpha { 4 } else { 3 };
+        let mut output = Vec::with_capacity(pixel_buffer_size(raster.width, raster.height, bytes_per_pixel)?);
+        for y in 0..raster.height {
+            for x in 0..raster.width {
+                let [red, green, blue, alpha] = pixel_rgba8(&raster, x, y, palette, transparency)?;
+                for (foreground, background) in [red, green, blue].into_iter().zip(background) {
+                    output.push(composite(foreground, background, alpha));
+                }
+                if with_alpha {
+                    output.push(255);
+                }
+            }
+        }
+        Ok((raster.width, raster.height, output))
+    }
+}
+
+pub fn pixel_rgba8(raster: &Raster, x: u32, y: u32, palette: Option<&[PaletteEntry]>, transparency: Option<&TransparencyInfo>) -> Result<[u8; 4], PngError> {
+    let scale = |channel: usize| scale_to_u8(raster.sample(x, y, channel), raster.bit_depth);
+    let [red, green, blue] = match raster.color_type {
+        ColorType::Grayscale | ColorType::GrayscaleAlpha => [scale(0); 3],
+        ColorType::RGB | ColorType::RGBA => [scale(0), scale(1), scale(2)],
+        ColorType::Indexed => palette
+            .ok_or_else(|| PngError::MissingChunk("PLTE".to_string()))?
+            .get(raster.sample(x, y, 0) as usize)
+            .ok_or_else(|| PngError::MalformedChunk("Palette index out of range".to_string()))?
+            .rgb(),
+    };
+    Ok([red, green, blue, pixel_alpha(raster, x, y, transparency)])
+}
+
+//Alpha over: alpha of 255 keeps the foreground, 0 shows only the background
+pub fn composite(foreground: u8, background: u8, alpha: u8) -> u8 {
+    ((foreground as u32 * alpha as u32 + background as u32 * (255 - alpha as u32) + 127) / 255) as u8
+}
+
+/*tRNS holds one alpha byte per palette index for indexed images, or a single 16 bit key colour (gray, or red green blue)
+that is fully transparent for grayscale and RGB images, every other pixel is opaque*/
+pub fn pixel_alpha(raster: &Raster, x: u32, y: u32, transparency: Option<&TransparencyInfo>) -> u8 {
+    match (raster.color_type, transparency) {
+        (ColorType::GrayscaleAlpha, _) => scale_to_u8(raster.sample(x, y, 1), raster.bit_depth),
+        (ColorType::RGBA, _) => scale_to_u8(raster.sample(x, y, 3), raster.bit_depth),
+        (ColorType::Indexed, Some(TransparencyInfo::Indexed(alphas))) => alphas.get(raster.sample(x, y, 0) as usize).copied().unwrap_or(255),
+        (ColorType::Grayscale, Some(&TransparencyInfo::Grayscale(gray))) if raster.sample(x, y, 0) == gray => 0,
+        (ColorType::RGB, Some(&TransparencyInfo::Rgb(red, green, blue)))
+            if [red, green, blue].into_iter().enumerate().all(|(channel, key)| raster.sample(x, y, channel) == key) => 0,
+        _ => 255,
+    }
+}
+
+/*Iterator over defiltered rows (packed like Raster rows). For non interlaced images the IDAT data is inflated one row at a time
+into two row buffers, the row being defiltered and the row above it, which swap places every row. So besides the compressed
+data only those two rows and the 32 KiB inflate window are held. Adam7 images can't produce a full row until the last pass
+so those are inflated and deinterlaced up front and handed out row by row*/
+pub struct Scanlines {
+    source: ScanlineSource,
+    check_adler: bool,
+    stride: usize,
+    bytes_per_pixel: usize,
+    rows: usize,
+    next_row: usize,
+    current: Vec<u8>,
+    previous: Vec<u8>,
+}
+
+enum ScanlineSource {
+    Filtered(ZlibReader<Vec<u8>>),
+    Deinterlaced(Vec<u8>),
+}
+
+impl Iterator for Scanlines {
+    type Item = Result<Vec<u8>, PngError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.next_row >= self.rows {
+            return None;
+        }
+        let y = self.next_row;
+        self.next_row += 1;
+
+        match &mut self.source {
+            ScanlineSource::Deinterlaced(data) => Some(Ok(data[y * self.stride..(y + 1) * self.stride].to_vec())),
+            ScanlineSource::Filtered(inflater) => {
+                std::mem::swap(&mut self.current, &mut self.previous);
+                let last_row = self.next_row == self.rows;
+                if let Err(e) = read_filtered_row(inflater, &mut self.current, &self.previous, self.bytes_per_pixel, last_row, self.check_adler) {
+                    self.next_row = self.rows;
+                    return Some(Err(e));
+                }
+                Some(Ok(self.current.clone()))
+            }
+        }
+    }
+}
+
+//Inflates the filter byte and the row into current and defilters it, the Adler-32 covers the whole stream so it's checked after the last row
+fn read_filtered_row(inflater: &mut ZlibReader<Vec<u8>>, current: &mut [u8], previous: &[u8], bytes_per_pixel: usize, last_row: bool, check_adler: bool) -> Result<(), PngError> {
+    let mut filter_type = [0];
+    if inflater.read(&mut filter_type)? + inflater.read(current)? < current.len() + 1 {
+        return Err(PngError::MalformedChunk("Decompressed image data is too short".to_string()));
+    }
+    unfilter_line(filter_type[0], current, previous, bytes_per_pixel)?;
+    if last_row {
+        inflater.finish(check_adler)?;
+    }
+    Ok(())
+}
+
+/*Each scanline starts with a filter type byte, the filters predict every byte from
+a (the byte one pixel to the left), b (the byte above) and c (the byte above and to the left), all zero outside the image.
+Filters work on bytes so pixels smaller than a byte count as one byte*/
+//after_row runs once each row is done and can stop the loop by returning an error
+pub(crate) fn unfilter_rows(filtered: &[u8], stride: usize, rows: usize, bytes_per_pixel: usize, mut after_row: impl FnMut() -> Result<(), PngError>) -> Result<Vec<u8>, PngError> {
+    if filtered.len() < buffer_size(stride + 1, rows)? {
+        return Err(PngError::MalformedChunk("Decompressed image data is too short".to_string()));
+    }
+    let mut output = vec![0u8; buffer_size(stride, rows)?];
+    let empty_row = vec![0u8; stride];
+
+    for y in 0..rows {
+        let line = &filtered[y * (stride + 1)..(y + 1) * (stride + 1)];
+        let (done, remaining) = output.split_at_mut(y * stride);
+        let prior = if y == 0 { &empty_row[..] } else { &done[(y - 1) * stride..] };
+        let current = &mut remaining[..stride];
+        current.copy_from_slice(&line[1..]);
+        unfilter_line(line[0], current, prior, bytes_per_pixel)?;
+        after_row()?;
+    }
+    Ok(output)
+}
+
+fn unfilter_line(filter_type: u8, current: &mut [u8], prior: &[u8], bytes_per_pixel: usize) -> Result<(), PngError> {
+    match filter_type {
+        0 => {}
+        1 => {
+            for i in bytes_per_pixel..current.len() {
+                current[i] = current[i].wrapping_add(current[i - bytes_per_pixel]);
+            }
+        }
+        2 => {
+            for i in 0..current.len() {
+                current[i] = current[i].wrapping_add(prior[i]);
+            }
+        }
+        3 => {
+            for i in 0..current.len() {
+                let left = if i >= bytes_per_pixel { current[i - bytes_per_pixel] } else { 0 };
+                current[i] = current[i].wrapping_add(((left as u16 + prior[i] as u16) / 2) as u8);
+            }
+        }
+        4 => {
+            for i in 0..current.len() {
+                let (left, upper_left) = if i >= bytes_per_pixel { (current[i - bytes_per_pixel], prior[i - bytes_per_pixel]) } else { (0, 0) };
+                current[i] = current[i].wrapping_add(paeth_predictor(left, prior[i], upper_left));
+            }
+        }
+        _ => return Err(PngError::MalformedChunk(format!("Unknown filter type {}", filter_type))),
+    }
+    Ok(())
+}
+
+//Ties go to a, then b, then c in that exact order as the spec requires
+pub(crate) fn paeth_predictor(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let pa = (p - a as i16).abs();
+    let pb = (p - b as i16).abs();
+    let pc = (p - c as i16).abs();
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+fn copy_pixel(source: &[u8], source_x: usize, destination: &mut [u8], destination_x: usize, bits_per_pixel: usize) {
+    if bits_per_pixel >= 8 {
+        let bytes = bits_per_pixel / 8;
+        destination[destination_x * bytes..(destination_x + 1) * bytes].copy_from_slice(&source[source_x * bytes..(source_x + 1) * bytes]);
+    } else {
+        let mask = ((1u16 << bits_per_pixel) - 1) as u8;
+        let source_bit = source_x * bits_per_pixel;
+        let value = (source[source_bit / 8] >> (8 - bits_per_pixel - source_bit % 8)) & mask;
+        let destination_bit = destination_x * bits_per_pixel;
+        let shift = 8 - bits_per_pixel - destination_bit % 8;
+        destination[destination_bit / 8] = (destination[destination_bit / 8] & !(mask << shift)) | (value << shift);
+    }
+}
@@ -0,0 +1,203 @@
+use std::io::{self, Write};
+use crate::png::error::PngError;
+use crate::Png;
+use crate::png::chunks::{Chunk, ColorType, PLTEChunk, tRNSChunk};
+use crate::png::decode::{pixel_buffer_size, pixel_rgba8, row_bytes, scale_to_u8};
+
+/*Layout of DecodedImage::data. 16 bit formats keep the samples big endian like the file does,
+Gray8 is also what 1, 2 and 4 bit grayscale come out as (scaled up by bit replication)*/
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PixelFormat {
+    Gray8,
+    GrayAlpha8,
+    Rgb8,
+    Rgba8,
+    Gray16,
+    GrayAlpha16,
+    Rgb16,
+    Rgba16,
+}
+
+impl PixelFormat {
+    pub fn channels(&self) -> usize {
+        match self {
+            PixelFormat::Gray8 | PixelFormat::Gray16 => 1,
+            PixelFormat::GrayAlpha8 | PixelFormat::GrayAlpha16 => 2,
+            PixelFormat::Rgb8 | PixelFormat::Rgb16 => 3,
+            PixelFormat::Rgba8 | PixelFormat::Rgba16 => 4,
+        }
+    }
+
+    pub fn bytes_per_sample(&self) -> usize {
+        match self {
+            PixelFormat::Gray8 | PixelFormat::GrayAlpha8 | PixelFormat::Rgb8 | PixelFormat::Rgba8 => 1,
+            _ => 2,
+        }
+    }
+}
+
+//Pixels in the closest format to what the file stores, rows are stride bytes apart
+#[derive(Debug)]
+pub struct DecodedImage {
+    pub width: u32,
+    pub height: u32,
+    pub stride: usize,
+    pub format: PixelFormat,
+    pub data: Vec<u8>,
+    pub gamma: Option<f64>, //File gamma from gAMA, None when the samples are sRGB encoded (an sRGB chunk or no gAMA at all)
+}
+
+impl<'a> Png<'a> {
+    /*Decodes to the image's own format instead of always RGBA8. 8 and 16 bit images keep their defiltered rows as they are,
+    sub byte grayscale is widened to Gray8 and indexed images are looked up in the palette (Rgba8 when tRNS gives the entries alpha, Rgb8 otherwise).
+    A grayscale/RGB tRNS key colour isn't applied, to_rgba8 does that*/
+    pub fn decode(&self) -> Result<DecodedImage, PngError> {
+        let raster = self.raster()?;
+        //sRGB wins over gAMA when both are there, same as in viewers
+        let gamma = if self.chunk_list.iter().any(|chunk| matches!(chunk, Chunk::sRGB(..))) { None } else { self.gamma() };
+        let sixteen_bit = raster.bit_depth == 16;
+        let format = match (raster.color_type, sixteen_bit) {
+            (ColorType::Grayscale, false) => PixelFormat::Gray8,
+            (ColorType::Grayscale, true) => PixelFormat::Gray16,
+            (ColorType::GrayscaleAlpha, false) => PixelFormat::GrayAlpha8,
+            (ColorType::GrayscaleAlpha, true) => PixelFormat::GrayAlpha16,
+            (ColorType::RGB, false) => PixelFormat::Rgb8,
+            (ColorType::RGB, true) => PixelFormat::Rgb16,
+            (ColorType::RGBA, false) => PixelFormat::Rgba8,
+            (ColorType::RGBA, true) => PixelFormat::Rgba16,
+            (ColorType::Indexed, _) if self.transparency().is_some() => PixelFormat::Rgba8,
+            (ColorType::Indexed, _) => PixelFormat::Rgb8,
+        };
+
+        if raster.color_type != ColorType::Indexed && raster.bit_depth >= 8 {
+            return Ok(DecodedImage { width: raster.width, height: raster.height, stride: raster.stride, format, data: raster.data, gamma });
+        }
+
+        let bytes_per_pixel = match format {
+            PixelFormat::Rgba8 => 4,
+            PixelFormat::Rgb8 => 3,
+            _ => 1,
+        };
+        let palette = self.palette().map(PLTEChunk::entries);
+        let transparency = self.transparency().map(tRNSChunk::transparency);
+        let mut data = Vec::with_capacity(pixel_buffer_size(raster.width, raster.height, bytes_per_pixel)?);
+        for y in 0..raster.height {
+            for x in 0..raster.width {
+                if raster.color_type == ColorType::Indexed {
+                    data.extend_from_slice(&pixel_rgba8(&raster, x, y, palette, transparency)?[..bytes_per_pixel]);
+                } else {
+                    data.push(scale_to_u8(raster.sample(x, y, 0), raster.bit_depth));
+                }
+            }
+        }
+        let stride = row_bytes(raster.width as usize, bytes_per_pixel * 8)?;
+        Ok(DecodedImage { width: raster.width, height: raster.height, stride, format, data, gamma })
+    }
+}
+
+impl DecodedImage {
+    /*RGBA of one pixel with 16 bit channels whatever the format, 8 bit samples are scaled by 257 so 255 becomes 65535.
+    Gray is copied into red, green and blue and formats without alpha are opaque. None when x or y is outside the image*/
+    pub fn get_pixel(&self, x: u32, y: u32) -> Option<[u16; 4]> {
+        if x >= self.width || y >= self.height {
+            return None;
+        }
+        let bytes_per_sample = self.format.bytes_per_sample();
+        let start = y as usize * self.stride + x as usize * self.format.channels() * bytes_per_sample;
+        let pixel = self.data.get(start..start + self.format.channels() * bytes_per_sample)?;
+        let sample = |channel: usize| match bytes_per_sample {
+            2 => u16::from_be_bytes([pixel[channel * 2], pixel[channel * 2 + 1]]),
+            _ => pixel[channel] as u16 * 257,
+        };
+        let rgba = match self.format.channels() {
+            1 => [sample(0), sample(0), sample(0), u16::MAX],
+            2 => [sample(0), sample(0), sample(0), sample(1)],
+            3 => [sample(0), sample(1), sample(2), u16::MAX],
+            _ => [sample(0), sample(1), sample(2), sample(3)],
+        };
+        Some(rgba)
+    }
+
+    //Binary P6 PPM for eyeballing the output in an image viewer, alpha is dropped and 16 bit samples keep their high byte
+    pub fn write_ppm<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        write!(w, "P6\n{} {}\n255\n", self.width, self.height)?;
+        let mut row = Vec::with_capacity(self.width as usize * 3);
+        for y in 0..self.height {
+            row.clear();
+            for x in 0..self.width {
+                let pixel = self.get_pixel(x, y).ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "pixel data is shorter than the image"))?;
+                row.extend(pixel[..3].iter().map(|&sample| (sample >> 8) as u8));
+            }
+            w.write_all(&row)?;
+        }
+        Ok(())
+    }
+
+    /*Rgb8 whatever the format, alpha is dropped and 16 bit samples keep their high byte. Indexed images were already looked up
+    in the palette by decode so this covers them too*/
+    pub fn to_rgb8(&self) -> DecodedImage {
+        let data = self.map_pixels(3, |[red, green, blue, _], data| data.extend_from_slice(&[red, green, blue]));
+        DecodedImage { width: self.width, height: self.height, stride: self.width as usize * 3, format: PixelFormat::Rgb8, data, gamma: self.gamma }
+    }
+
+    //Gray8 with colour weighted as luma (0.299 R + 0.587 G + 0.114 B), gray formats just lose any alpha
+    pub fn to_grayscale8(&self) -> DecodedImage {
+        let data = self.map_pixels(1, |[red, green, blue, _], data| {
+            let luma = 0.299 * red as f64 + 0.587 * green as f64 + 0.114 * blue as f64;
+            data.push(luma.round().min(255.0) as u8);
+        });
+        DecodedImage { width: self.width, height: self.height, stride: self.width as usize, format: PixelFormat::Gray8, data, gamma: self.gamma }
+    }
+
+    //RGBA8 of every pixel handed to convert to append in the new format, unpadded rows. The fields are public so a data buffer cut short comes out black past its end
+    fn map_pixels(&self, bytes_per_pixel: usize, convert: impl Fn([u8; 4], &mut Vec<u8>)) -> Vec<u8> {
+        let mut data = Vec::with_capacity(self.width as usize * self.height as usize * bytes_per_pixel);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let rgba = self.get_pixel(x, y).unwrap_or([0, 0, 0, u16::MAX]).map(|sample| (sample >> 8) as u8);
+                convert(rgba, &mut data);
+            }
+        }
+        data
+    }
+
+    /*Samples scaled to 0.0 - 1.0 with no row padding. The file stores light^gamma (gamma is usually 0.45455) so sample^(1 / gamma) gets the
+    light back, then ^(1 / display_gamma) encodes it for the display. A display_gamma of 1.0 gives linear light, 2.2 gives values ready for
+    a typical monitor. Alpha is already linear so it's only scaled*/
+    pub fn to_linear(&self, display_gamma: f64) -> Vec<f32> {
+        let channels = self.format.channels();
+        let has_alpha = channels == 2 || channels == 4;
+        let bytes_per_sample = self.format.bytes_per_sample();
+        let max_value = if bytes_per_sample == 2 { 65535.0 } else { 255.0 };
+        let to_light = |encoded: f64| match self.gamma {
+            Some(gamma) => encoded.powf(1.0 / gamma),
+            None => decode_srgb(encoded),
+        };
+
+        let samples_per_row = self.width as usize * channels;
+        let mut linear = Vec::with_capacity(samples_per_row * self.height as usize);
+        for row in self.data.chunks(self.stride).take(self.height as usize) {
+            for (index, sample) in row.chunks_exact(bytes_per_sample).take(samples_per_row).enumerate() {
+                let value = match sample {
+                    [high, low] => u16::from_be_bytes([*high, *low]) as f64,
+                    _ => sample[0] as f64,
+                } / max_value;
+                if has_alpha && index % channels == channels - 1 {
+                    linear.push(value as f32);
+                } else {
+                    linear.push(to_light(value).powf(1.0 / display_gamma) as f32);
+                }
+            }
+        }
+        linear
+    }
+}
+
+//sRGB's curve has a short linear part near black so it isn't quite a plain 2.2 power
+fn decode_srgb(encoded: f64) -> f64 {
+    if encoded <= 0.04045 {
+        encoded / 12.92
+    } else {
+        ((encoded + 0.055) / 1.055).powf(2.4)
+    }
+}
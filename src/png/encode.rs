@@ -0,0 +1,52 @@
+use crate::png::chunks::Chunk;
+use crate::png::crc::crc32;
+use crate::png::error::PngError;
+use crate::png::png::PNG_SIGNATURE;
+use crate::Png;
+
+//Chunk laid out as it is in a file: length (4 bytes), type (4 bytes), data, then the CRC of the type and data
+pub(crate) fn build_chunk(chunk_type: &[u8; 4], data: &[u8]) -> Vec<u8> {
+    let mut chunk = Vec::with_capacity(data.len() + 12);
+    chunk.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    chunk.extend_from_slice(chunk_type);
+    chunk.extend_from_slice(data);
+    chunk.extend_from_slice(&crc32(&chunk[4..]).to_be_bytes());
+    chunk
+}
+
+//Signature followed by already built chunks, the caller is in charge of chunk order
+pub(crate) fn build_png(chunks: &[Vec<u8>]) -> Vec<u8> {
+    let mut png = PNG_SIGNATURE.to_vec();
+    for chunk in chunks {
+        png.extend_from_slice(chunk);
+    }
+    png
+}
+
+impl<'a> Png<'a> {
+    /*Signature and every chunk in chunk_list written back out in order with freshly computed CRCs, so chunks can be removed from
+    chunk_list (a tEXt for example) before saving. An untouched file comes back byte for byte*/
+    pub fn to_bytes(&self) -> Result<Vec<u8>, PngError> {
+        let mut chunks = Vec::with_capacity(self.chunk_list.len());
+        for chunk in &self.chunk_list {
+            let data = chunk.serialize();
+            //Lengths are limited to 2^31 - 1 by the spec even though the field has room for more
+            if data.len() > i32::MAX as usize {
+                return Err(PngError::LimitExceeded(format!("{} chunk of {} bytes is too long to write", String::from_utf8_lossy(&chunk.chunk_type()), data.len())));
+            }
+            chunks.push(build_chunk(&chunk.chunk_type(), &data));
+        }
+        Ok(build_png(&chunks))
+    }
+
+    /*Drops text, EXIF and timestamp chunks plus any ancillary chunk we couldn't parse, since there's no telling what those hold.
+    Critical chunks, color chunks (gAMA, cHRM, sRGB, iCCP, tRNS, bKGD...) and animation chunks are kept so the image still looks
+    the same once written with to_bytes*/
+    pub fn strip_metadata(&mut self) {
+        self.chunk_list.retain(|chunk| match chunk {
+            Chunk::tEXt(..) | Chunk::zTXt(..) | Chunk::iTXt(..) | Chunk::eXIf(..) | Chunk::tIME(..) => false,
+            Chunk::Unknown(..) => chunk.is_critical(),
+            _ => true,
+        });
+    }
+}
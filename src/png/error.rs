@@ -0,0 +1,128 @@
+use std::error::Error;
+use std::fmt;
+use std::string::FromUtf8Error;
+use crate::png::chunks::ColorType;
+
+//Everything that can go wrong while reading or decoding a PNG, the variants holding a String carry the full message
+#[derive(Debug)]
+pub enum PngError {
+    InvalidSignature { found: [u8; 8] }, //First 8 bytes of the file, zero padded if it's shorter
+    UnexpectedChunk(String), //Critical chunk type this decoder doesn't know
+    ChunkOrder(String),
+    IhdrNotFirst(String), //Type of the chunk found first instead
+    MissingChunk(String),
+    DuplicateChunk(String),
+    NoImageData,
+    CrcMismatch { chunk_type: String, offset: usize, expected: u32, found: u32 }, //offset is where the chunk's length field starts
+    UnexpectedEof { chunk_type: Option<String>, at: usize, needed: usize, available: usize }, //at is the file offset the read started from
+    MissingIend,
+    TruncatedIend,
+    TrailingData(usize), //Bytes left in the file after IEND
+    InvalidColorType(u8),
+    InvalidBitDepth { color_type: ColorType, bit_depth: u8 },
+    InvalidInterlaceMethod(u8),
+    InvalidStereoMode(u8),
+    InvalidTime(String), //Which tIME field is out of range
+    UnsupportedCompressionMethod(u8),
+    UnsupportedFilterMethod(u8),
+    MalformedChunk(String),
+    Decompression(String),
+    LimitExceeded(String),
+    ChunkTooLarge { chunk_type: String, length: u32, max: u32 }, //Length field over DecodeOptions::max_chunk_size
+    InvalidArgument(String),
+    Cancelled,
+    Utf8(FromUtf8Error),
+    Io(std::io::Error),
+}
+
+impl fmt::Display for PngError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PngError::InvalidSignature { found } => write!(f, "Invalid PNG signature, found {:02X?}", found),
+            PngError::UnexpectedChunk(chunk_type) => write!(f, "Unexpected critical chunk type: {}", chunk_type),
+            PngError::IhdrNotFirst(chunk_type) => write!(f, "IHDR must be the first chunk, found {}", chunk_type),
+            PngError::MissingChunk(chunk_type) => write!(f, "{} chunk not found", chunk_type),
+            PngError::DuplicateChunk(chunk_type) => write!(f, "{} chunk appears more than once", chunk_type),
+            PngError::NoImageData => write!(f, "no image data (no IDAT chunk)"),
+            PngError::CrcMismatch { chunk_type, offset, expected, found } => {
+                write!(f, "CRC mismatch for {} at offset {}: computed 0x{:08X}, found 0x{:08X}", chunk_type, offset, expected, found)
+            }
+            PngError::UnexpectedEof { chunk_type, at, needed, available } => {
+                write!(f, "unexpected EOF")?;
+                if let Some(chunk_type) = chunk_type {
+                    write!(f, " in {} chunk", chunk_type)?;
+                }
+                write!(f, " at offset {}: needed {} bytes but only {} are left", at, needed, available)
+            }
+            PngError::MissingIend => write!(f, "unexpected EOF: IEND not found"),
+            PngError::TruncatedIend => write!(f, "truncated IEND: missing CRC"),
+            PngError::TrailingData(length) => write!(f, "{} bytes after IEND", length),
+            PngError::ChunkTooLarge { chunk_type, length, max } => write!(f, "{} chunk length {} exceeds the limit of {} bytes", chunk_type, length, max),
+            PngError::InvalidColorType(color_type) => write!(f, "Unknown color type: {}", color_type),
+            PngError::InvalidBitDepth { color_type, bit_depth } => write!(f, "Bit depth {} is not allowed for color type {:?}", bit_depth, color_type),
+            PngError::InvalidInterlaceMethod(interlace_method) => write!(f, "Unknown interlace method: {}", interlace_method),
+            PngError::InvalidStereoMode(stereo_mode) => write!(f, "Unknown sTER mode: {}", stereo_mode),
+            PngError::InvalidTime(field) => write!(f, "Invalid tIME {}", field),
+            PngError::UnsupportedCompressionMethod(compression_method) => write!(f, "unsupported compression method {}", compression_method),
+            PngError::UnsupportedFilterMethod(filter_method) => write!(f, "unsupported filter method {}", filter_method),
+            PngError::ChunkOrder(message)
+            | PngError::MalformedChunk(message)
+            | PngError::Decompression(message)
+            | PngError::LimitExceeded(message)
+            | PngError::InvalidArgument(message) => write!(f, "{}", message),
+            PngError::Cancelled => write!(f, "Decode cancelled"),
+            PngError::Utf8(e) => write!(f, "{}", e),
+            PngError::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl PngError {
+    //Shifts the file offsets in errors from a buffer holding only part of the file (from_reader's current chunk), base is where that buffer starts
+    pub(crate) fn offset_by(self, base: usize) -> Self {
+        match self {
+            PngError::CrcMismatch { chunk_type, offset, expected, found } => PngError::CrcMismatch { chunk_type, offset: base + offset, expected, found },
+            PngError::UnexpectedEof { chunk_type, at, needed, available } => PngError::UnexpectedEof { chunk_type, at: base + at, needed, available },
+            other => other,
+        }
+    }
+
+    pub(crate) fn invalid_signature(leading_bytes: &[u8]) -> Self {
+        let mut found = [0; 8];
+        let length = leading_bytes.len().min(8);
+        found[..length].copy_from_slice(&leading_bytes[..length]);
+        PngError::InvalidSignature { found }
+    }
+
+    //Names the chunk being read for EOFs that happened inside one, other errors already say which chunk they're about
+    pub(crate) fn in_chunk(self, chunk_type: &[u8; 4]) -> Self {
+        match self {
+            PngError::UnexpectedEof { chunk_type: None, at, needed, available } => {
+                PngError::UnexpectedEof { chunk_type: Some(String::from_utf8_lossy(chunk_type).into_owned()), at, needed, available }
+            }
+            other => other,
+        }
+    }
+}
+
+impl Error for PngError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            PngError::Utf8(e) => Some(e),
+            PngError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<FromUtf8Error> for PngError {
+    fn from(e: FromUtf8Error) -> Self {
+        PngError::Utf8(e)
+    }
+}
+
+impl From<std::io::Error> for PngError {
+    fn from(e: std::io::Error) -> Self {
+        PngError::Io(e)
+    }
+}
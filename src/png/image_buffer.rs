@@ -0,0 +1,20 @@
+use image::RgbaImage;
+use crate::png::error::PngError;
+use crate::Png;
+
+//Hands the RGBA8 output of to_rgba8 over to the image crate, only built with the image feature
+impl<'a> Png<'a> {
+    pub fn to_image_buffer(&self) -> Result<RgbaImage, PngError> {
+        let (width, height, pixels) = self.to_rgba8()?;
+        //from_raw only refuses a buffer smaller than width * height * 4, which to_rgba8 never hands back
+        RgbaImage::from_raw(width, height, pixels).ok_or_else(|| PngError::LimitExceeded(format!("{}x{} image doesn't fit in an ImageBuffer", width, height)))
+    }
+}
+
+impl TryFrom<&Png<'_>> for RgbaImage {
+    type Error = PngError;
+
+    fn try_from(png: &Png<'_>) -> Result<Self, Self::Error> {
+        png.to_image_buffer()
+    }
+}
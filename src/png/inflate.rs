@@ -0,0 +1,395 @@
+use crate::png::error::PngError;
+
+/*zlib stream (RFC 1950) wrapping raw DEFLATE data (RFC 1951). Layout:
+- CMF (1 byte, low 4 bits compression method which must be 8, high 4 bits window size)
+- FLG (1 byte, CMF * 256 + FLG must be a multiple of 31, bit 5 is a preset dictionary which PNG never uses)
+- compressed blocks
+- Adler-32 of the uncompressed data (4 bytes, MSB first)
+max_output stops a small stream that inflates to gigabytes (a zip bomb) as soon as the output goes past it, None means no limit*/
+pub fn zlib_decompress(data: &[u8], check_adler: bool, max_output: Option<usize>) -> Result<Vec<u8>, PngError> {
+    ZlibReader::new(data, max_output)?.finish(check_adler)
+}
+
+//Back-references reach at most this far behind the current output position
+const WINDOW_SIZE: usize = 32 * 1024;
+
+/*Inflates on demand so the output can be taken a piece at a time (Scanlines takes a row at a time) instead of all at once.
+Once bytes have been taken only the last WINDOW_SIZE of them are kept around for back-references, zlib_decompress just runs it to the end*/
+pub(crate) struct ZlibReader<D: AsRef<[u8]>> {
+    reader: BitReader<D>,
+    block: Block,
+    last_block: bool,
+    output: Vec<u8>, //Inflated bytes, everything before taken has been handed out and is only kept as history
+    taken: usize,
+    discarded: usize, //Bytes dropped from the front of output, they still count towards max_output
+    max_output: usize,
+    adler: Adler32, //Over the discarded bytes, the rest is added in finish
+}
+
+enum Block {
+    Header,
+    Codes(Huffman, Huffman),
+    Done,
+}
+
+impl<D: AsRef<[u8]>> ZlibReader<D> {
+    //Checks the 2 byte zlib header, max_output of None means no limit
+    pub(crate) fn new(data: D, max_output: Option<usize>) -> Result<Self, PngError> {
+        let bytes = data.as_ref();
+        if bytes.len() < 6 {
+            return Err(PngError::Decompression("zlib stream is too short".to_string()));
+        }
+        let cmf = bytes[0];
+        let flg = bytes[1];
+        if !(cmf as u16 * 256 + flg as u16).is_multiple_of(31) {
+            return Err(PngError::Decompression("zlib header check bits are invalid".to_string()));
+        }
+        if cmf & 0x0F != 8 || cmf >> 4 > 7 {
+            return Err(PngError::Decompression("zlib compression method is not DEFLATE".to_string()));
+        }
+        if flg & 0x20 != 0 {
+            return Err(PngError::Decompression("zlib preset dictionaries are not allowed in PNG".to_string()));
+        }
+
+        Ok(Self {
+            reader: BitReader::new(data, 2),
+            block: Block::Header,
+            last_block: false,
+            output: Vec::new(),
+            taken: 0,
+            discarded: 0,
+            max_output: max_output.unwrap_or(usize::MAX),
+            adler: Adler32::new(),
+        })
+    }
+
+    //Fills buffer from the start and returns how many bytes went in, less than its length only once the stream has ended
+    pub(crate) fn read(&mut self, buffer: &mut [u8]) -> Result<usize, PngError> {
+        self.fill(buffer.len())?;
+        let count = buffer.len().min(self.output.len() - self.taken);
+        buffer[..count].copy_from_slice(&self.output[self.taken..self.taken + count]);
+        self.taken += count;
+
+        //Dropped in big steps so the front of output isn't shifted for every read
+        if self.taken > 2 * WINDOW_SIZE {
+            let dropped = self.taken - WINDOW_SIZE;
+            self.adler.update(&self.output[..dropped]);
+            self.output.drain(..dropped);
+            self.taken -= dropped;
+            self.discarded += dropped;
+        }
+        Ok(count)
+    }
+
+    //Inflates whatever is left, checks the Adler-32 of the whole stream and returns the bytes that were never read
+    pub(crate) fn finish(&mut self, check_adler: bool) -> Result<Vec<u8>, PngError> {
+        self.fill(usize::MAX)?;
+        self.reader.align_to_byte();
+        let stored = self.reader.read_bytes(4).map_err(|_| PngError::Decompression("zlib stream is missing its Adler-32".to_string()))?;
+        let stored = u32::from_be_bytes([stored[0], stored[1], stored[2], stored[3]]);
+        let mut adler = self.adler.clone();
+        adler.update(&self.output);
+        let computed = adler.finalize();
+        if check_adler && stored != computed {
+            return Err(PngError::Decompression(format!("Adler-32 mismatch: computed 0x{:08X}, found 0x{:08X}", computed, stored)));
+        }
+        Ok(self.output.split_off(self.taken))
+    }
+
+    /*Every block starts with a final block bit and a 2 bit type:
+    0 stored (no compression), 1 fixed Huffman codes, 2 dynamic Huffman codes sent before the data, 3 is reserved.
+    Stops once wanted bytes are waiting to be read or the last block is done*/
+    fn fill(&mut self, wanted: usize) -> Result<(), PngError> {
+        while self.output.len() - self.taken < wanted {
+            self.block = match std::mem::replace(&mut self.block, Block::Done) {
+                Block::Header => {
+                    self.last_block = self.reader.bits(1)? == 1;
+                    match self.reader.bits(2)? {
+                        0 => {
+                            self.inflate_stored()?;
+                            self.after_block()
+                        }
+                        1 => {
+                            let (literals, distances) = fixed_codes()?;
+                            Block::Codes(literals, distances)
+                        }
+                        2 => {
+                            let (literals, distances) = dynamic_codes(&mut self.reader)?;
+                            Block::Codes(literals, distances)
+                        }
+                        _ => return Err(PngError::Decompression("Invalid DEFLATE block type".to_string())),
+                    }
+                }
+                Block::Codes(literals, distances) => {
+                    if self.inflate_codes(&literals, &distances, wanted)? {
+                        self.after_block()
+                    } else {
+                        Block::Codes(literals, distances)
+                    }
+                }
+                Block::Done => return Ok(()),
+            };
+        }
+        Ok(())
+    }
+
+    fn after_block(&self) -> Block {
+        if self.last_block {
+            Block::Done
+        } else {
+            Block::Header
+        }
+    }
+
+    //Checked before the bytes are written so the output never grows past max_output
+    fn check_output_limit(&self, adding: usize) -> Result<(), PngError> {
+        if (self.discarded + self.output.len()).saturating_add(adding) > self.max_output {
+            return Err(PngError::LimitExceeded(format!("Decompressed data is larger than the {} byte limit", self.max_output)));
+        }
+        Ok(())
+    }
+
+    fn inflate_stored(&mut self) -> Result<(), PngError> {
+        self.reader.align_to_byte();
+        let header = self.reader.read_bytes(4)?;
+        let length = u16::from_le_bytes([header[0], header[1]]);
+        let complement = u16::from_le_bytes([header[2], header[3]]);
+        if length != !complement {
+            return Err(PngError::Decompression("Stored block length does not match its complement".to_string()));
+        }
+        self.check_output_limit(length as usize)?;
+        let bytes = self.reader.read_bytes(length as usize)?;
+        self.output.extend_from_slice(bytes);
+        Ok(())
+    }
+
+    //True once the end of block code is reached, false if it stopped early because wanted bytes are ready
+    fn inflate_codes(&mut self, literals: &Huffman, distances: &Huffman, wanted: usize) -> Result<bool, PngError> {
+        while self.output.len() - self.taken < wanted {
+            let symbol = literals.decode(&mut self.reader)? as usize;
+            if symbol < 256 {
+                self.check_output_limit(1)?;
+                self.output.push(symbol as u8);
+            } else if symbol == 256 {
+                return Ok(true);
+            } else {
+                let symbol = symbol - 257;
+                if symbol >= 29 {
+                    return Err(PngError::Decompression("Invalid DEFLATE length code".to_string()));
+                }
+                let length = LENGTH_BASE[symbol] as usize + self.reader.bits(LENGTH_EXTRA[symbol] as u32)? as usize;
+
+                let distance_symbol = distances.decode(&mut self.reader)? as usize;
+                if distance_symbol >= 30 {
+                    return Err(PngError::Decompression("Invalid DEFLATE distance code".to_string()));
+                }
+                let distance = DISTANCE_BASE[distance_symbol] as usize + self.reader.bits(DISTANCE_EXTRA[distance_symbol] as u32)? as usize;
+                //At least WINDOW_SIZE bytes of history stay in output once anything is discarded so this only trips near the start
+                if distance > self.output.len() {
+                    return Err(PngError::Decompression("DEFLATE distance points before the start of the output".to_string()));
+                }
+
+                self.check_output_limit(length)?;
+                //Copied one byte at a time because the match is allowed to overlap the bytes it is producing
+                let start = self.output.len() - distance;
+                for i in 0..length {
+                    self.output.push(self.output[start + i]);
+                }
+            }
+        }
+        Ok(false)
+    }
+}
+
+pub fn adler32(bytes: &[u8]) -> u32 {
+    let mut adler = Adler32::new();
+    adler.update(bytes);
+    adler.finalize()
+}
+
+//Two running sums modulo the largest prime below 2^16, the modulo is deferred for 5552 bytes which is the most that can't overflow a u32
+#[derive(Debug, Clone)]
+pub struct Adler32 {
+    a: u32,
+    b: u32,
+}
+
+impl Adler32 {
+    pub fn new() -> Self {
+        Self { a: 1, b: 0 }
+    }
+
+    pub fn update(&mut self, bytes: &[u8]) {
+        for block in bytes.chunks(5552) {
+            for &byte in block {
+                self.a += byte as u32;
+                self.b += self.a;
+            }
+            self.a %= 65521;
+            self.b %= 65521;
+        }
+    }
+
+    pub fn finalize(self) -> u32 {
+        (self.b << 16) | self.a
+    }
+}
+
+impl Default for Adler32 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+//DEFLATE packs bits starting from the least significant bit of each byte
+struct BitReader<D: AsRef<[u8]>> {
+    data: D,
+    position: usize,
+    bit_buffer: u32,
+    bit_count: u32,
+}
+
+impl<D: AsRef<[u8]>> BitReader<D> {
+    fn new(data: D, position: usize) -> Self {
+        Self { data, position, bit_buffer: 0, bit_count: 0 }
+    }
+
+    fn bits(&mut self, count: u32) -> Result<u32, PngError> {
+        while self.bit_count < count {
+            let byte = *self.data.as_ref().get(self.position).ok_or_else(|| PngError::Decompression("Unexpected end of DEFLATE data".to_string()))?;
+            self.position += 1;
+            self.bit_buffer |= (byte as u32) << self.bit_count;
+            self.bit_count += 8;
+        }
+        let value = self.bit_buffer & ((1u32 << count) - 1);
+        self.bit_buffer >>= count;
+        self.bit_count -= count;
+        Ok(value)
+    }
+
+    //Stored blocks start on a byte boundary so any bits left in the current byte are thrown away
+    fn align_to_byte(&mut self) {
+        self.bit_buffer = 0;
+        self.bit_count = 0;
+    }
+
+    fn read_bytes(&mut self, count: usize) -> Result<&[u8], PngError> {
+        let bytes = self.data.as_ref().get(self.position..self.position + count).ok_or_else(|| PngError::Decompression("Unexpected end of DEFLATE data".to_string()))?;
+        self.position += count;
+        Ok(bytes)
+    }
+}
+
+/*Canonical Huffman code stored as the number of codes of each length and the symbols ordered by code,
+decoding walks one bit at a time comparing against the first code of each length*/
+struct Huffman {
+    counts: [u16; 16],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Result<Self, PngError> {
+        let mut counts = [0u16; 16];
+        for &length in lengths {
+            counts[length as usize] += 1;
+        }
+        counts[0] = 0;
+
+        let mut left = 1i32;
+        for &count in &counts[1..] {
+            left <<= 1;
+            left -= count as i32;
+            if left < 0 {
+                return Err(PngError::Decompression("Over-subscribed Huffman code".to_string()));
+            }
+        }
+
+        let mut offsets = [0u16; 16];
+        for length in 1..15 {
+            offsets[length + 1] = offsets[length] + counts[length];
+        }
+        let mut symbols = vec![0u16; lengths.len()];
+        for (symbol, &length) in lengths.iter().enumerate() {
+            if length != 0 {
+                symbols[offsets[length as usize] as usize] = symbol as u16;
+                offsets[length as usize] += 1;
+            }
+        }
+        Ok(Self { counts, symbols })
+    }
+
+    fn decode<D: AsRef<[u8]>>(&self, reader: &mut BitReader<D>) -> Result<u16, PngError> {
+        let mut code = 0i32;
+        let mut first = 0i32;
+        let mut index = 0i32;
+        for length in 1..16 {
+            code |= reader.bits(1)? as i32;
+            let count = self.counts[length] as i32;
+            if code - count < first {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first += count;
+            first <<= 1;
+            code <<= 1;
+        }
+        Err(PngError::Decompression("Invalid Huffman code".to_string()))
+    }
+}
+
+const LENGTH_BASE: [u16; 29] = [3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258];
+const LENGTH_EXTRA: [u8; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+const DISTANCE_BASE: [u16; 30] = [1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577];
+const DISTANCE_EXTRA: [u8; 30] = [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13];
+//Order the code length code lengths are stored in for dynamic blocks
+const CODE_LENGTH_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+fn fixed_codes() -> Result<(Huffman, Huffman), PngError> {
+    let mut lengths = [0u8; 288];
+    lengths[..144].fill(8);
+    lengths[144..256].fill(9);
+    lengths[256..280].fill(7);
+    lengths[280..].fill(8);
+    Ok((Huffman::new(&lengths)?, Huffman::new(&[5u8; 30])?))
+}
+
+fn dynamic_codes<D: AsRef<[u8]>>(reader: &mut BitReader<D>) -> Result<(Huffman, Huffman), PngError> {
+    let literal_count = reader.bits(5)? as usize + 257;
+    let distance_count = reader.bits(5)? as usize + 1;
+    let code_length_count = reader.bits(4)? as usize + 4;
+    if literal_count > 286 || distance_count > 30 {
+        return Err(PngError::Decompression("Too many Huffman codes in dynamic block".to_string()));
+    }
+
+    let mut code_length_lengths = [0u8; 19];
+    for &index in &CODE_LENGTH_ORDER[..code_length_count] {
+        code_length_lengths[index] = reader.bits(3)? as u8;
+    }
+    let code_lengths = Huffman::new(&code_length_lengths)?;
+
+    //Literal/length and distance code lengths are sent as one run so repeats can cross between them
+    let mut lengths = vec![0u8; literal_count + distance_count];
+    let mut index = 0;
+    while index < lengths.len() {
+        let symbol = code_lengths.decode(reader)?;
+        let (value, repeat) = match symbol {
+            0..=15 => (symbol as u8, 1),
+            16 => {
+                let previous = *lengths[..index].last().ok_or_else(|| PngError::Decompression("Repeat code with no previous length".to_string()))?;
+                (previous, 3 + reader.bits(2)? as usize)
+            }
+            17 => (0, 3 + reader.bits(3)? as usize),
+            _ => (0, 11 + reader.bits(7)? as usize),
+        };
+        if index + repeat > lengths.len() {
+            return Err(PngError::Decompression("Code length repeat runs past the end of the table".to_string()));
+        }
+        lengths[index..index + repeat].fill(value);
+        index += repeat;
+    }
+    if lengths[256] == 0 {
+        return Err(PngError::Decompression("Dynamic block has no end of block code".to_string()));
+    }
+
+    Ok((Huffman::new(&lengths[..literal_count])?, Huffman::new(&lengths[literal_count..])?))
+}
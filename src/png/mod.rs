@@ -1,4 +1,21 @@
 pub mod png;
+pub mod error;
 pub mod chunks;
-pub use chunks::{IDHRChunk, PLTEChunk, IDATChunk, IENDChunk, tIMEChunk, bKGDChunk, gAMAChunk, cHRMChunk, dSIGChunk, eXIfChunk, hISTChunk,
-    iCCPChunk, iTXtChunk, pHYsChunk, sBITChunk, sPLTChunk, sRGBChunk, sTERChunk, tEXtChunk, tRNSChunk, zTXtChunk, Chunk};
+pub mod crc;
+pub mod inflate;
+pub mod decode;
+pub mod options;
+pub mod compare;
+pub mod encode;
+pub mod visitor;
+pub mod borrowed;
+pub mod animation;
+pub mod decoded;
+#[cfg(feature = "color-management")]
+pub mod color;
+#[cfg(feature = "image")]
+pub mod image_buffer;
+#[cfg(test)]
+mod tests;
+pub use chunks::{IHDRChunk, PLTEChunk, IDATChunk, IENDChunk, tIMEChunk, bKGDChunk, gAMAChunk, cHRMChunk, dSIGChunk, eXIfChunk, hISTChunk,
+    iCCPChunk, iTXtChunk, pHYsChunk, sBITChunk, sPLTChunk, sRGBChunk, sTERChunk, tEXtChunk, tRNSChunk, zTXtChunk, acTLChunk, fcTLChunk, fdATChunk, sCALChunk, UnknownChunk, Chunk};
@@ -0,0 +1,146 @@
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use crate::png::error::PngError;
+
+//Strict turns problems that can be worked around (like a broken ancillary chunk) into errors
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Strictness {
+    Lenient,
+    Strict,
+}
+
+/*Settings for a decode, built fluently from the defaults:
+DecodeOptions::default().ignore_crc(true).max_pixels(Some(4096 * 4096))
+The defaults are meant for untrusted input so every limit is on and CRC/Adler-32 checking is on*/
+#[derive(Debug, Clone)]
+pub struct DecodeOptions {
+    pub(crate) ignore_crc: bool,
+    pub(crate) ignore_adler: bool,
+    pub(crate) max_chunks: Option<usize>,
+    pub(crate) max_pixels: Option<u64>,
+    pub(crate) max_chunk_size: Option<u32>,
+    pub(crate) max_decompressed_metadata: Option<usize>,
+    pub(crate) strictness: Strictness,
+    pub(crate) cancellation_flag: Option<Arc<AtomicBool>>,
+    pub(crate) treat_as_raw: HashSet<[u8; 4]>,
+    pub(crate) skip_leading_garbage: Option<usize>,
+    pub(crate) strict_ordering: bool,
+    pub(crate) tolerate_unknown: bool,
+    pub(crate) allow_trailing_data: bool,
+}
+
+impl Default for DecodeOptions {
+    fn default() -> Self {
+        Self {
+            ignore_crc: false,
+            ignore_adler: false,
+            max_chunks: Some(100_000),
+            max_pixels: Some(16384 * 16384),
+            max_chunk_size: Some(64 * 1024 * 1024),
+            max_decompressed_metadata: Some(8 * 1024 * 1024),
+            strictness: Strictness::Lenient,
+            cancellation_flag: None,
+            treat_as_raw: HashSet::new(),
+            skip_leading_garbage: None,
+            strict_ordering: false,
+            tolerate_unknown: false,
+            allow_trailing_data: false,
+        }
+    }
+}
+
+impl DecodeOptions {
+    pub fn ignore_crc(mut self, ignore_crc: bool) -> Self {
+        self.ignore_crc = ignore_crc;
+        self
+    }
+
+    pub fn ignore_adler(mut self, ignore_adler: bool) -> Self {
+        self.ignore_adler = ignore_adler;
+        self
+    }
+
+    //None removes the limit
+    pub fn max_chunks(mut self, max_chunks: Option<usize>) -> Self {
+        self.max_chunks = max_chunks;
+        self
+    }
+
+    pub fn max_pixels(mut self, max_pixels: Option<u64>) -> Self {
+        self.max_pixels = max_pixels;
+        self
+    }
+
+    pub fn max_chunk_size(mut self, max_chunk_size: Option<u32>) -> Self {
+        self.max_chunk_size = max_chunk_size;
+        self
+    }
+
+    //Limit on what a single zTXt, iTXt or iCCP chunk may inflate to, their compressed size says nothing about that
+    pub fn max_decompressed_metadata(mut self, max_decompressed_metadata: Option<usize>) -> Self {
+        self.max_decompressed_metadata = max_decompressed_metadata;
+        self
+    }
+
+    pub fn strictness(mut self, strictness: Strictness) -> Self {
+        self.strictness = strictness;
+        self
+    }
+
+    //Setting the flag from another thread stops the decode at the next chunk or decoding stage
+    pub fn cancellation_flag(mut self, cancellation_flag: Arc<AtomicBool>) -> Self {
+        self.cancellation_flag = Some(cancellation_flag);
+        self
+    }
+
+    //Chunks of this type are kept as UnknownChunk bytes even if there's a parser for them, handy for skipping big sPLT/iCCP chunks
+    pub fn treat_as_raw(mut self, chunk_type: [u8; 4]) -> Self {
+        self.treat_as_raw.insert(chunk_type);
+        self
+    }
+
+    //Scan up to this many bytes into the file for the signature when it isn't right at the start
+    pub fn skip_leading_garbage(mut self, max_offset: Option<usize>) -> Self {
+        self.skip_leading_garbage = max_offset;
+        self
+    }
+
+    //Checks the spec's placement rules (gAMA before PLTE, tRNS before IDAT, IDATs in a row...) which a lot of writers get wrong harmlessly
+    pub fn strict_ordering(mut self, strict_ordering: bool) -> Self {
+        self.strict_ordering = strict_ordering;
+        self
+    }
+
+    //Keeps unknown critical chunks as UnknownChunk with a warning instead of failing, the image may not come out right
+    pub fn tolerate_unknown(mut self, tolerate_unknown: bool) -> Self {
+        self.tolerate_unknown = tolerate_unknown;
+        self
+    }
+
+    //Bytes after IEND become a warning instead of TrailingData, for files with junk appended by a download or a tool
+    pub fn allow_trailing_data(mut self, allow_trailing_data: bool) -> Self {
+        self.allow_trailing_data = allow_trailing_data;
+        self
+    }
+
+    //ChunkTooLarge for a length over max_chunk_size, checked before anything is allocated for the chunk
+    pub(crate) fn check_chunk_size(&self, length: u32, chunk_type: &[u8]) -> Result<(), PngError> {
+        if let Some(max) = self.max_chunk_size.filter(|&max_chunk_size| length > max_chunk_size) {
+            return Err(PngError::ChunkTooLarge { chunk_type: String::from_utf8_lossy(chunk_type).into_owned(), length, max });
+        }
+        Ok(())
+    }
+
+    //Called before each chunk with the number already read
+    pub(crate) fn check_chunk_count(&self, chunks_read: usize) -> Result<(), PngError> {
+        if self.max_chunks.is_some_and(|max_chunks| chunks_read >= max_chunks) {
+            return Err(PngError::LimitExceeded("Too many chunks".to_string()));
+        }
+        Ok(())
+    }
+
+    pub(crate) fn is_cancelled(&self) -> bool {
+        self.cancellation_flag.as_ref().is_some_and(|flag| flag.load(Ordering::Relaxed))
+    }
+}
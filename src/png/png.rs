@@ -1,15 +1,24 @@
 
-use std::error::Error;
+use std::cell::OnceCell;
+use crate::png::error::PngError;
 use std::ffi::OsStr;
+use std::fmt;
 use std::fs::File;
 use std::io::Read;
 use std::path::Path;
 use crate::Chunk;
+use crate::png::chunks::{is_critical_type, Chromaticities, IDATChunk, IENDChunk, IHDRChunk, PLTEChunk, SuggestedPalette, tEXtChunk, tRNSChunk};
+use crate::png::crc::{crc32, Crc32};
+use crate::png::options::{DecodeOptions, Strictness};
+
+//Every PNG file starts with these 8 bytes
+pub const PNG_SIGNATURE: [u8; 8] = [137, 80, 78, 71, 13, 10, 26, 10];
 
 //Stream going to be used to assign to every png file to sequentially read data
-#[derive(Debug)]
+#[derive(Debug, Default, Clone)]
 struct Stream {
     sequential_counter: usize,
+    chunk_end: usize, //Position of the current chunk's CRC, set once its length and type have been read
 }
 
 impl Stream {
@@ -19,75 +28,520 @@ impl Stream {
         }
     }
     //Reads bytes sequentially and updates a counter every time we read bytes
-    fn read_bytes_sequential(&mut self, byte_list: &Vec<u8>, range: usize) -> Result<Vec<u8>, Box<dyn Error>> {
+    fn read_bytes_sequential(&mut self, byte_list: &[u8], range: usize) -> Result<Vec<u8>, PngError> {
+        self.read_slice_sequential(byte_list, range).map(<[u8]>::to_vec)
+    }
+
+    //Same as read_bytes_sequential but borrows from byte_list instead of copying
+    fn read_slice_sequential<'d>(&mut self, byte_list: &'d [u8], range: usize) -> Result<&'d [u8], PngError> {
         let start = self.sequential_counter;
-        let end = self.sequential_counter + range;
-        if byte_list.len() >= end {
-            self.sequential_counter += range;
-            Ok(byte_list[start..end].to_vec())
-        } else {
-            Err("Range is out of bounds".into())
+        let bytes = start
+            .checked_add(range)
+            .and_then(|end| byte_list.get(start..end))
+            .ok_or_else(|| self.eof_error(byte_list, range))?;
+        self.sequential_counter += range;
+        Ok(bytes)
+    }
+
+    //Moves past bytes without reading them, with the same bounds check as a read
+    fn skip(&mut self, byte_list: &[u8], range: usize) -> Result<(), PngError> {
+        match self.sequential_counter.checked_add(range) {
+            Some(end) if end <= byte_list.len() => {
+                self.sequential_counter = end;
+                Ok(())
+            }
+            _ => Err(self.eof_error(byte_list, range)),
         }
     }
+
+    fn position(&self) -> usize {
+        self.sequential_counter
+    }
+
+    fn eof_error(&self, byte_list: &[u8], range: usize) -> PngError {
+        let at = self.sequential_counter;
+        PngError::UnexpectedEof { chunk_type: None, at, needed: range, available: byte_list.len().saturating_sub(at) }
+    }
+
+    fn remaining_in_chunk(&self) -> Result<usize, PngError> {
+        self.chunk_end
+            .checked_sub(self.sequential_counter)
+            .ok_or_else(|| PngError::MalformedChunk("Read past the end of the chunk".to_string()))
+    }
 }
 
-impl Default for Stream {
-    fn default() -> Stream {
-        Stream {
-            sequential_counter: 0,
+//Problem with an ancillary chunk that a lenient decode worked around instead of failing
+#[derive(Debug, Clone)]
+pub struct DecodeWarning {
+    pub chunk_type: String,
+    pub reason: String,
+}
+
+/*Colour chunks that contradict each other. sRGB fixes the gamma and chromaticities so a gAMA or cHRM next to it should carry
+the sRGB values, and an embedded ICC profile can't be used alongside sRGB*/
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ColorSpaceConflict {
+    SrgbWithIccp,
+    GammaMismatch { gamma: f64 }, //What gAMA says instead of 0.45455
+    ChromaticitiesMismatch(Chromaticities),
+}
+
+impl fmt::Display for ColorSpaceConflict {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ColorSpaceConflict::SrgbWithIccp => write!(f, "sRGB present together with iCCP"),
+            ColorSpaceConflict::GammaMismatch { gamma } => write!(f, "gAMA {} is inconsistent with sRGB", gamma),
+            ColorSpaceConflict::ChromaticitiesMismatch(..) => write!(f, "cHRM is inconsistent with sRGB"),
         }
     }
 }
 
-#[derive(Debug)]
+/*File size against what the parsed chunks account for (8 byte signature + each chunk's data and 12 bytes of length/type/CRC).
+A positive discrepancy is data after IEND (or junk skipped before the signature), a negative one means the chunks claim more than the file holds*/
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SizeReport {
+    pub file_size: u64,
+    pub chunk_bytes: u64,
+    pub discrepancy: i64,
+}
+
+#[derive(Debug, Clone)]
 pub struct Png<'a> {
     file: FileLoader<'a>,
     data_stream: Stream,
     pub chunk_list: Vec<Chunk>,
     signature_verified: bool,
-    png_signature: Vec<u8>,
+    chunks_start: usize, //Offset of the first chunk, past the signature and any leading garbage that was skipped
+    options: DecodeOptions,
+    rgba_cache: OnceCell<(u32, u32, Vec<u8>)>, //Filled the first time rows() needs the decoded image
+    warnings: Vec<DecodeWarning>,
 }
 
 impl<'a> Png<'a> {
-    pub fn new(file_name: &'a str) -> Self {
-        let file = FileLoader::load_file(&file_name).expect("Failed to open file");
-        let mut stream = Stream::new();
-        let signature = &stream
-            .read_bytes_sequential(&file.data, 8)
-            .expect("Failed to read bytes");
-        let mut verified = false;
-        let mut chunk_list = Vec::new();
-        if signature == &vec![137, 80, 78, 71, 13, 10, 26, 10] {
-            verified = true;
+    pub fn new(file_name: &'a str) -> Result<Self, PngError> {
+        Self::open_unverified(file_name)?.require_signature()
+    }
+
+    //Same as new but for data that's already in memory (downloaded, embedded, etc.) so nothing touches the filesystem
+    pub fn from_bytes(data: Vec<u8>) -> Result<Self, PngError> {
+        Self::from_bytes_unverified(data)?.require_signature()
+    }
+
+    //Same as decode_file_with for a file that's already in memory, the chunks are parsed right away unlike from_bytes
+    pub fn with_options(data: Vec<u8>, options: DecodeOptions) -> Result<Png<'static>, PngError> {
+        crate::parse_with(Png::from_bytes_unverified(data)?, options)
+    }
+
+    //Loads without failing on a bad signature, the decoder uses this so skip_leading_garbage gets a chance to find the real one
+    pub(crate) fn open_unverified(file_name: &'a str) -> Result<Self, PngError> {
+        let file = FileLoader::load_file(file_name)?;
+        let mut png = Self::from_bytes_unverified(file.data)?;
+        png.file.file_name = Some(file_name);
+        Ok(png)
+    }
+
+    fn require_signature(self) -> Result<Self, PngError> {
+        if !self.signature_verified {
+            return Err(PngError::invalid_signature(self.leading_bytes()));
         }
-        Self {
-            file: file,
+        Ok(self)
+    }
+
+    pub(crate) fn from_bytes_unverified(data: Vec<u8>) -> Result<Self, PngError> {
+        //Too short to even hold the signature, reported as a bad signature with what was there rather than an EOF
+        if data.len() < PNG_SIGNATURE.len() {
+            return Err(PngError::invalid_signature(&data));
+        }
+        let file = FileLoader { file_name: None, size: data.len(), data };
+        let mut stream = Stream::new();
+        stream.read_bytes_sequential(&file.data, 8)?; //Moves the stream past the signature, the bytes are checked by verify_signature
+        let chunk_list = Vec::new();
+        let mut png = Self {
+            file,
             data_stream: stream,
-            chunk_list: chunk_list,
-            signature_verified: verified,
-            png_signature: [137, 80, 78, 71, 13, 10, 26, 10].to_vec(),
+            chunk_list,
+            signature_verified: false,
+            chunks_start: PNG_SIGNATURE.len(),
+            options: DecodeOptions::default(),
+            rgba_cache: OnceCell::new(),
+            warnings: Vec::new(),
+        };
+        png.signature_verified = png.verify_signature();
+        Ok(png)
+    }
+
+    /*For pipes and sockets, reads and parses one chunk at a time using the length fields so only the chunk being parsed is
+    buffered (the parsed chunks are kept as usual), and stops right after IEND so anything the source sends afterwards is left unread.
+    No internal buffering for that reason, wrap slow readers in a BufReader yourself*/
+    pub fn from_reader<R: Read>(reader: R) -> Result<Png<'static>, PngError> {
+        Self::from_reader_with_options(reader, DecodeOptions::default())
+    }
+
+    //Same as from_reader with the given limits and strictness, skip_leading_garbage doesn't apply since the signature has to come first
+    pub fn from_reader_with_options<R: Read>(mut reader: R, options: DecodeOptions) -> Result<Png<'static>, PngError> {
+        let mut signature = Vec::with_capacity(PNG_SIGNATURE.len());
+        reader.by_ref().take(PNG_SIGNATURE.len() as u64).read_to_end(&mut signature)?;
+        if signature != PNG_SIGNATURE {
+            return Err(PngError::invalid_signature(&signature));
+        }
+        crate::parse_from_reader(Png::from_bytes_unverified(signature)?, reader, options)
+    }
+
+    /*Fast path for when only the size and pixels matter (thumbnails). IHDR, PLTE, tRNS, IDAT and IEND are parsed as usual but every other
+    ancillary chunk is stepped over using its length field without being read or CRC checked, so none of them end up in chunk_list*/
+    pub fn parse_headers_only(&mut self) -> Result<(), PngError> {
+        loop {
+            if self.remaining_bytes() == 0 {
+                return Err(PngError::MissingIend);
+            }
+            let chunk_start = self.position();
+            let length = self.big_endian_u32()?;
+            let key_bytes = self.read_bytes(4)?;
+            let chunk_type = [key_bytes[0], key_bytes[1], key_bytes[2], key_bytes[3]];
+            self.options.check_chunk_size(length, &chunk_type)?;
+            let type_name = String::from_utf8_lossy(&chunk_type).into_owned();
+            if self.chunk_list.is_empty() && &chunk_type != b"IHDR" {
+                return Err(PngError::IhdrNotFirst(type_name));
+            }
+
+            self.begin_chunk(length);
+            let chunk = match &chunk_type {
+                b"IHDR" => {
+                    let IHDR_chunk = IHDRChunk::new(length, self)?;
+                    let pixels = IHDR_chunk.width() as u64 * IHDR_chunk.height() as u64;
+                    if self.options.max_pixels.is_some_and(|max_pixels| pixels > max_pixels) {
+                        return Err(PngError::LimitExceeded(format!("Image has {} pixels which exceeds the limit", pixels)));
+                    }
+                    Chunk::IHDR(IHDR_chunk)
+                }
+                b"PLTE" => Chunk::PLTE(PLTEChunk::new(length, self)?),
+                //Ancillary but it changes what the pixels come out as, a broken one is dropped with a warning in lenient mode
+                b"tRNS" => match tRNSChunk::new(length, self).and_then(|tRNS_chunk| self.check_chunk_consumed(chunk_start, length, &chunk_type).map(|()| tRNS_chunk)) {
+                    Ok(tRNS_chunk) => Chunk::tRNS(tRNS_chunk),
+                    Err(e) if self.options.strictness == Strictness::Lenient => {
+                        self.add_warning(DecodeWarning { chunk_type: type_name, reason: e.to_string() });
+                        self.seek(chunk_start + 8)?;
+                        self.skip(length as usize + 4).map_err(|e| e.in_chunk(&chunk_type))?;
+                        continue;
+                    }
+                    Err(e) => return Err(e),
+                },
+                b"IDAT" => Chunk::IDAT(IDATChunk::new(length, self)?),
+                b"IEND" => Chunk::IEND(IENDChunk::new(length, self)?),
+                _ if is_critical_type(&chunk_type) => return Err(PngError::UnexpectedChunk(type_name)),
+                _ => {
+                    self.skip(length as usize + 4).map_err(|e| e.in_chunk(&chunk_type))?;
+                    continue;
+                }
+            };
+            self.check_chunk_consumed(chunk_start, length, &chunk_type)?;
+            if !self.options.ignore_crc {
+                self.check_crc(chunk_start, length)?;
+            }
+            self.add_chunk(chunk)?;
+            if &chunk_type == b"IEND" {
+                return Ok(());
+            }
         }
     }
 
-    pub fn get_string(&mut self, length: usize) -> Result<String, Box<dyn Error>> {
+    pub fn options(&self) -> &DecodeOptions {
+        &self.options
+    }
+
+    pub fn set_options(&mut self, options: DecodeOptions) {
+        self.options = options;
+    }
+
+    pub(crate) fn rgba_cache(&self) -> &OnceCell<(u32, u32, Vec<u8>)> {
+        &self.rgba_cache
+    }
+
+    pub fn warnings(&self) -> &[DecodeWarning] {
+        &self.warnings
+    }
+
+    pub fn add_warning(&mut self, warning: DecodeWarning) {
+        self.warnings.push(warning);
+    }
+
+    pub fn signature_verified(&self) -> bool {
+        self.signature_verified
+    }
+
+    pub fn leading_bytes(&self) -> &[u8] {
+        &self.file.data[..self.file.data.len().min(8)]
+    }
+
+    //Looks for the signature within the first max_offset bytes (for files with a BOM or transfer junk in front) and continues after it
+    pub fn skip_leading_garbage(&mut self, max_offset: usize) -> bool {
+        let search_end = self.file.data.len().min(max_offset + 8);
+        let found = self.file.data[..search_end]
+            .windows(8)
+            .position(|window| window == PNG_SIGNATURE);
+        if let Some(offset) = found {
+            self.data_stream.sequential_counter = offset + 8;
+            self.chunks_start = offset + 8;
+            self.signature_verified = true;
+        }
+        self.signature_verified
+    }
+
+    pub fn get_string(&mut self, length: usize) -> Result<String, PngError> {
         let bytes = self.read_bytes(length)?;
         String::from_utf8(bytes).map_err(Into::into)
     }
 
 
-    pub fn add_chunk(&mut self, chunk: Chunk) -> Result<(), Box<dyn Error>> {
+    pub fn add_chunk(&mut self, chunk: Chunk) -> Result<(), PngError> {
         self.chunk_list.push(chunk);
         Ok(())
     }
-    pub fn read_bytes(&mut self, range: usize) -> Result<Vec<u8>, Box<dyn Error>> {
-        self.data_stream.read_bytes_sequential(&self.file.data, range)
+
+    pub fn find_ihdr(&self) -> Option<&IHDRChunk> {
+        self.chunk_list.iter().find_map(|chunk| match chunk {
+            Chunk::IHDR(IHDR_chunk) => Some(IHDR_chunk),
+            _ => None,
+        })
+    }
+
+    //Every chunk with the given type bytes in file order, works for unknown chunks too
+    pub fn chunks_of_type(&self, chunk_type: [u8; 4]) -> impl Iterator<Item = &Chunk> {
+        self.chunk_list.iter().filter(move |chunk| chunk.chunk_type() == chunk_type)
+    }
+
+    pub fn text_chunks(&self) -> impl Iterator<Item = &tEXtChunk> {
+        self.chunk_list.iter().filter_map(|chunk| match chunk {
+            Chunk::tEXt(text_chunk) => Some(text_chunk),
+            _ => None,
+        })
     }
 
-    pub fn big_endian_u32(&mut self) -> Result<u32, Box<dyn Error>> {
-        let bytes = self.read_bytes(4)?;
-        if bytes.len() != 4 {
-            return Err(Box::new(std::io::Error::new(std::io::ErrorKind::InvalidData, "Not enough bytes to read a u32",)));
+    pub fn idat_chunks(&self) -> impl Iterator<Item = &IDATChunk> {
+        self.chunk_list.iter().filter_map(|chunk| match chunk {
+            Chunk::IDAT(IDAT_chunk) => Some(IDAT_chunk),
+            _ => None,
+        })
+    }
+
+    /*Drops the colour space chunks so a display just assumes sRGB. gAMA, cHRM and iCCP always go,
+    sRGB can be kept since it agrees with that assumption anyway*/
+    pub fn strip_color_chunks(&mut self, keep_srgb: bool) {
+        self.chunk_list.retain(|chunk| match chunk {
+            Chunk::gAMA(..) | Chunk::cHRM(..) | Chunk::iCCP(..) => false,
+            Chunk::sRGB(..) => keep_srgb,
+            _ => true,
+        });
+    }
+
+    //Every sPLT chunk in file order, there can be several as long as their names differ
+    pub fn suggested_palettes(&self) -> Vec<SuggestedPalette> {
+        self.chunk_list.iter()
+            .filter_map(|chunk| match chunk {
+                Chunk::sPLT(splt_chunk) => Some(splt_chunk.suggested_palette()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /*(keyword, text) of every tEXt chunk in file order. Keywords can legally repeat (several "Comment" entries say)
+    so duplicates are all kept rather than collapsed into one*/
+    pub fn text_entries(&self) -> Vec<(&str, &str)> {
+        self.text_chunks()
+            .map(|text_chunk| (text_chunk.keyword(), text_chunk.text()))
+            .collect()
+    }
+
+    pub fn gamma(&self) -> Option<f64> {
+        self.chunk_list.iter().find_map(|chunk| match chunk {
+            Chunk::gAMA(gAMA_chunk) => Some(gAMA_chunk.gamma_value()),
+            _ => None,
+        })
+    }
+
+    //Inflated iCCP profile, Ok(None) when the file doesn't embed one. Limited by DecodeOptions::max_decompressed_metadata
+    pub fn icc_profile(&self) -> Result<Option<Vec<u8>>, PngError> {
+        self.chunk_list.iter()
+            .find_map(|chunk| match chunk {
+                Chunk::iCCP(iCCP_chunk) => Some(iCCP_chunk.icc_profile(self.options().max_decompressed_metadata)),
+                _ => None,
+            })
+            .transpose()
+    }
+
+    //Reported rather than treated as errors since viewers just use sRGB (or iCCP) and ignore the rest
+    pub fn color_space_conflicts(&self) -> Vec<ColorSpaceConflict> {
+        let mut conflicts = Vec::new();
+        if !self.chunk_list.iter().any(|chunk| matches!(chunk, Chunk::sRGB(..))) {
+            return conflicts;
         }
+        //Writers round differently (45454 and 45455 are both common) so only differences past the 5th decimal count
+        const TOLERANCE: f64 = 0.001;
+        const SRGB_GAMMA: f64 = 0.45455;
+        const SRGB_CHROMATICITIES: [f64; 8] = [0.3127, 0.329, 0.64, 0.33, 0.3, 0.6, 0.15, 0.06];
+
+        for chunk in &self.chunk_list {
+            match chunk {
+                Chunk::iCCP(..) => conflicts.push(ColorSpaceConflict::SrgbWithIccp),
+                Chunk::gAMA(gAMA_chunk) if (gAMA_chunk.gamma_value() - SRGB_GAMMA).abs() > TOLERANCE => {
+                    conflicts.push(ColorSpaceConflict::GammaMismatch { gamma: gAMA_chunk.gamma_value() });
+                }
+                Chunk::cHRM(cHRM_chunk) => {
+                    let chromaticities = cHRM_chunk.chromaticities();
+                    let Chromaticities { white_x, white_y, red_x, red_y, green_x, green_y, blue_x, blue_y } = chromaticities;
+                    let values = [white_x, white_y, red_x, red_y, green_x, green_y, blue_x, blue_y];
+                    if values.iter().zip(SRGB_CHROMATICITIES).any(|(value, expected)| (value - expected).abs() > TOLERANCE) {
+                        conflicts.push(ColorSpaceConflict::ChromaticitiesMismatch(chromaticities));
+                    }
+                }
+                _ => {}
+            }
+        }
+        conflicts
+    }
+
+    //Width and height from IHDR, None until the chunks have been parsed
+    pub fn dimensions(&self) -> Option<(u32, u32)> {
+        self.find_ihdr().map(|IHDR_chunk| (IHDR_chunk.width(), IHDR_chunk.height()))
+    }
+
+    pub fn size_report(&self) -> SizeReport {
+        let file_size = self.file.size as u64;
+        let chunk_bytes: u64 = self.chunk_list.iter().map(|chunk| chunk.length() as u64 + 12).sum();
+        let accounted = PNG_SIGNATURE.len() as u64 + chunk_bytes;
+        SizeReport { file_size, chunk_bytes, discrepancy: file_size as i64 - accounted as i64 }
+    }
+
+    /*Physical print size from IHDR's pixel size and pHYs. pHYs counts pixels per metre so inches are width / (pixels per metre * 0.0254),
+    None without pHYs, when its unit isn't metres (it's only an aspect ratio then) or when a density is 0*/
+    pub fn print_size_inches(&self) -> Option<(f64, f64)> {
+        let (width, height) = self.dimensions()?;
+        let pHYs_chunk = self.chunk_list.iter().find_map(|chunk| match chunk {
+            Chunk::pHYs(pHYs_chunk) => Some(pHYs_chunk),
+            _ => None,
+        })?;
+        let (x_dpi, y_dpi) = pHYs_chunk.dpi()?;
+        Some((width as f64 / x_dpi, height as f64 / y_dpi))
+    }
+
+    /*Offset of the first chunk of the given type found by walking the chunk headers and skipping over the data, nothing is parsed.
+    The stream is put back where it was afterwards so this can be called before or after the chunks are read*/
+    pub fn find_chunk_offset(&mut self, chunk_type: &[u8; 4]) -> Result<Option<usize>, PngError> {
+        let saved_position = self.position();
+        let found = self.scan_for_chunk(chunk_type);
+        self.data_stream.sequential_counter = saved_position;
+        found
+    }
+
+    fn scan_for_chunk(&mut self, chunk_type: &[u8; 4]) -> Result<Option<usize>, PngError> {
+        self.seek(self.chunks_start)?;
+        while self.remaining_bytes() >= 8 {
+            let chunk_start = self.position();
+            let length = self.big_endian_u32()?;
+            let found_type = self.read_bytes(4)?;
+            if found_type == chunk_type {
+                return Ok(Some(chunk_start));
+            }
+            if found_type == b"IEND" {
+                break;
+            }
+            self.seek(self.position() + length as usize + 4)?;
+        }
+        Ok(None)
+    }
+
+    //Called by the decoder after reading a chunk's length and type so parsers can read up to the CRC without doing the length math themselves
+    pub fn begin_chunk(&mut self, length: u32) {
+        self.data_stream.chunk_end = self.data_stream.sequential_counter + length as usize;
+    }
+
+    //Used by from_reader, the next chunk replaces the previous one's bytes and reading starts over at its length field
+    pub(crate) fn load_chunk(&mut self, chunk: Vec<u8>) {
+        self.file.size += chunk.len();
+        self.file.data = chunk;
+        self.data_stream = Stream::new();
+    }
+
+    pub fn remaining_in_chunk(&self) -> Result<usize, PngError> {
+        self.data_stream.remaining_in_chunk()
+    }
+
+    pub fn read_to_chunk_end(&mut self) -> Result<Vec<u8>, PngError> {
+        let remaining = self.data_stream.remaining_in_chunk()?;
+        self.read_bytes(remaining)
+    }
+
+    pub fn position(&self) -> usize {
+        self.data_stream.position()
+    }
+
+    pub fn skip(&mut self, range: usize) -> Result<(), PngError> {
+        self.data_stream.skip(&self.file.data, range)
+    }
+
+    pub fn seek(&mut self, position: usize) -> Result<(), PngError> {
+        let file_length = self.file.data.len();
+        if position > file_length {
+            return Err(PngError::UnexpectedEof { chunk_type: None, at: file_length, needed: position - file_length, available: 0 });
+        }
+        self.data_stream.sequential_counter = position;
+        Ok(())
+    }
+
+    pub fn remaining_bytes(&self) -> usize {
+        self.file.data.len().saturating_sub(self.data_stream.sequential_counter)
+    }
+
+    /*Chunk parsers read their own CRC so they should stop right after it, anywhere else means the length field and the chunk's
+    fields disagree. The CRC the parser took is then from the wrong place too, so the caller has to seek back before carrying on*/
+    pub(crate) fn check_chunk_consumed(&self, chunk_start: usize, length: u32, chunk_type: &[u8; 4]) -> Result<(), PngError> {
+        let expected_end = chunk_start + 12 + length as usize;
+        if self.position() != expected_end {
+            let read = self.position().saturating_sub(chunk_start + 12);
+            return Err(PngError::MalformedChunk(format!("{} chunk length {} doesn't match the {} bytes its fields take",
+                String::from_utf8_lossy(chunk_type), length, read)));
+        }
+        Ok(())
+    }
+
+    //Works from the raw file bytes rather than the parsed chunk so a chunk parser misreading its length can't hide a bad CRC
+    pub fn check_crc(&self, chunk_start: usize, length: u32) -> Result<(), PngError> {
+        let type_start = chunk_start + 4;
+        let crc_start = type_start + 4 + length as usize;
+        let data = &self.file.data;
+        if data.len() < crc_start + 4 {
+            let chunk_type = data.get(type_start..type_start + 4).map(|bytes| String::from_utf8_lossy(bytes).into_owned());
+            return Err(PngError::UnexpectedEof { chunk_type, at: crc_start, needed: 4, available: data.len().saturating_sub(crc_start) });
+        }
+
+        let found = u32::from_be_bytes([data[crc_start], data[crc_start + 1], data[crc_start + 2], data[crc_start + 3]]);
+        if !self.verify_crc(&data[type_start..type_start + 4], &data[type_start + 4..crc_start], found) {
+            let computed = crc32(&data[type_start..crc_start]);
+            let chunk_type = String::from_utf8_lossy(&data[type_start..type_start + 4]).into_owned();
+            return Err(PngError::CrcMismatch { chunk_type, offset: chunk_start, expected: computed, found });
+        }
+        Ok(())
+    }
+
+    //The CRC covers the type bytes followed by the data, never the length
+    pub fn verify_crc(&self, chunk_type: &[u8], chunk_data: &[u8], stored_crc: u32) -> bool {
+        let mut crc = Crc32::new();
+        crc.update(chunk_type);
+        crc.update(chunk_data);
+        crc.finalize() == stored_crc
+    }
+
+    pub fn read_bytes(&mut self, range: usize) -> Result<Vec<u8>, PngError> {
+        self.data_stream.read_bytes_sequential(&self.file.data, range)
+    }
+
+    //Borrows straight from the file data, for the small fixed size reads that would otherwise allocate a Vec each time
+    pub fn read_slice(&mut self, range: usize) -> Result<&[u8], PngError> {
+        self.data_stream.read_slice_sequential(&self.file.data, range)
+    }
+
+    pub fn big_endian_u32(&mut self) -> Result<u32, PngError> {
+        let bytes = self.read_slice(4)?;
 
         Ok(((bytes[0] as u32) << 24)
             | ((bytes[1] as u32) << 16)
@@ -95,73 +549,65 @@ impl<'a> Png<'a> {
             | (bytes[3] as u32))
     }
 
-    pub fn big_endian_u16(&mut self) -> Result<u16, Box<dyn Error>> {
-        let bytes = self.read_bytes(2)?;
-        if bytes.len() != 2 {
-            return Err(Box::new(std::io::Error::new(std::io::ErrorKind::InvalidData, "Not enough bytes to read a u16",)));
-        }
+    pub fn big_endian_u16(&mut self) -> Result<u16, PngError> {
+        let bytes = self.read_slice(2)?;
 
         Ok(((bytes[0] as u16) << 8)
             | (bytes[1] as u16))
     }
 
-    pub fn get_u32(&mut self) -> Result<Vec<u8>, Box<dyn Error>> {
-        let bytes = self.read_bytes(4)?;
-        if bytes.is_empty() {
-            return Err(Box::new(std::io::Error::new(std::io::ErrorKind::InvalidData, "Not enough bytes to read a u32",)));
-        }
-        Ok(bytes)
+    pub fn get_u32(&mut self) -> Result<[u8; 4], PngError> {
+        let bytes = self.read_slice(4)?;
+        Ok([bytes[0], bytes[1], bytes[2], bytes[3]])
     }
 
-    pub fn get_u16(&mut self) -> Result<Vec<u8>, Box<dyn Error>> {
-        let bytes = self.read_bytes(2)?;
-        if bytes.is_empty() {
-            return Err(Box::new(std::io::Error::new(std::io::ErrorKind::InvalidData, "Not enough bytes to read a u16")));
-        }
-        Ok(bytes)
+    pub fn get_u16(&mut self) -> Result<[u8; 2], PngError> {
+        let bytes = self.read_slice(2)?;
+        Ok([bytes[0], bytes[1]])
     }
 
-    pub fn get_u8(&mut self) -> Result<u8, Box<dyn Error>> {
-        let bytes = self.read_bytes(1)?;
-        if bytes.is_empty() {
-            return Err(Box::new(std::io::Error::new(std::io::ErrorKind::InvalidData, "Not enough bytes to read a u8")));
-        }
-        Ok(bytes[0])
+    pub fn get_u8(&mut self) -> Result<u8, PngError> {
+        Ok(self.read_slice(1)?[0])
     }
 
-    pub fn read_null_terminated_string(&mut self) -> Result<(String, u32), Box<dyn Error>> {
-        let mut bytes = Vec::new();
-        let mut byte = self.get_u8()?;
-        while byte != 0 {
-            bytes.push(byte);
-            byte = self.get_u8()?;
-        }
+    pub fn read_null_terminated_string(&mut self) -> Result<(String, u32), PngError> {
+        let bytes = self.read_null_terminated_bytes()?;
         let length = bytes.len() as u32;
         let string = String::from_utf8(bytes)?;
         Ok((string, length))
     }
 
-
-    fn verify_signature(mut self) -> Self {
-        let mut buf = vec![0; 8]; //8 Byte buff
-        let mut file = File::open(&self.file.file_name).expect("Can't open file");
-        file.read_exact(&mut buf).expect("Can't read from file");
-
-        if buf == vec![137, 80, 78, 71, 13, 10, 26, 10] {
-            println!("Signature is correct");
-            self.signature_verified = true;
+    //Raw bytes before the terminator for fields that aren't UTF-8 (tEXt and zTXt are Latin-1)
+    //Stops at the chunk end so a missing terminator can't run on into the next chunk's bytes
+    pub fn read_null_terminated_bytes(&mut self) -> Result<Vec<u8>, PngError> {
+        let mut bytes = Vec::new();
+        loop {
+            if self.remaining_in_chunk()? == 0 {
+                return Err(PngError::MalformedChunk("Null terminator missing before the end of the chunk".to_string()));
+            }
+            let byte = self.get_u8()?;
+            if byte == 0 {
+                break;
+            }
+            bytes.push(byte);
         }
+        Ok(bytes)
+    }
+
 
-        self
+    //Checks the bytes already loaded rather than opening the file again
+    pub fn verify_signature(&self) -> bool {
+        self.file.data.starts_with(&PNG_SIGNATURE)
     }
 }
 
 //idk why I've decided to use lifetimes here but I wanted to use the str variable so I'm forced to, only using this shit because it's stack allocated instead of heap
 //Seperate struct so in the future I can handle file loads and deloads for potential optimisation/error checking
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 struct FileLoader<'a> {
-    file_name: &'a str,
+    file_name: Option<&'a str>, //None when the data came from from_bytes
     data: Vec<u8>,
+    size: usize, //Same as data.len() except from_reader, where data only holds the last chunk read
 }
 
 impl<'a> FileLoader<'a> {
@@ -170,13 +616,14 @@ impl<'a> FileLoader<'a> {
         let mut buffer = Vec::new();
         file_data.read_to_end(&mut buffer)?;
         Ok(Self {
-            file_name: f_name,
+            file_name: Some(f_name),
+            size: buffer.len(),
             data: buffer,
         })
     }
 
     fn get_extension_from_filename(&self) -> Option<&str> {
-        Path::new(self.file_name)
+        Path::new(self.file_name?)
             .extension()
             .and_then(OsStr::to_str)
     }
@@ -0,0 +1,502 @@
+use crate::png::borrowed::{parse_borrowed, parse_borrowed_with};
+use crate::png::chunks::Chunk;
+use crate::png::crc::{crc32, Crc32};
+use crate::png::decode::{paeth_predictor, unfilter_rows};
+use crate::png::encode::{build_chunk, build_png};
+use crate::png::error::PngError;
+use crate::png::inflate::{adler32, zlib_decompress, Adler32};
+use crate::png::options::{DecodeOptions, Strictness};
+use crate::Png;
+
+fn ihdr(width: u32, height: u32, bit_depth: u8, color_type: u8) -> Vec<u8> {
+    let mut data = width.to_be_bytes().to_vec();
+    data.extend_from_slice(&height.to_be_bytes());
+    data.extend_from_slice(&[bit_depth, color_type, 0, 0, 0]);
+    build_chunk(b"IHDR", &data)
+}
+
+//zlib stream with a single stored (uncompressed) block, enough for the tiny images here
+fn stored_zlib(raw: &[u8]) -> Vec<u8> {
+    let mut zlib = vec![0x78, 0x01, 1];
+    zlib.extend_from_slice(&(raw.len() as u16).to_le_bytes());
+    zlib.extend_from_slice(&(!(raw.len() as u16)).to_le_bytes());
+    zlib.extend_from_slice(raw);
+    zlib.extend_from_slice(&adler32(raw).to_be_bytes());
+    zlib
+}
+
+//Just enough of a fixed Huffman DEFLATE encoder to make streams with back-references
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    bit_count: usize,
+}
+
+impl BitWriter {
+    fn bits(&mut self, value: u32, count: usize) {
+        for i in 0..count {
+            if self.bit_count.is_multiple_of(8) {
+                self.bytes.push(0);
+            }
+            *self.bytes.last_mut().unwrap() |= (((value >> i) & 1) as u8) << (self.bit_count % 8);
+            self.bit_count += 1;
+        }
+    }
+
+    //Huffman codes are the one thing sent most significant bit first
+    fn code(&mut self, code: u32, length: usize) {
+        for i in (0..length).rev() {
+            self.bits(code >> i, 1);
+        }
+    }
+}
+
+//raw has to repeat every distance bytes, the first distance bytes go out as literals and the rest as 258 byte copies where they fit
+fn repeating_zlib(raw: &[u8], distance: usize) -> Vec<u8> {
+    assert!(distance > 4);
+    let mut writer = BitWriter::default();
+    writer.bits(1, 1);
+    writer.bits(1, 2);
+    let mut position = 0;
+    while position < raw.len() {
+        if position >= distance && raw.len() - position >= 258 {
+            assert_eq!(raw[position..position + 258], raw[position - distance..position - distance + 258]);
+            writer.code(0xC5, 8); //Length symbol 285, 258 bytes with no extra bits
+            let offset = distance as u32 - 1;
+            let extra_bits = offset.ilog2() - 1;
+            writer.code(2 * offset.ilog2() + ((offset >> extra_bits) & 1), 5);
+            writer.bits(offset & ((1 << extra_bits) - 1), extra_bits as usize);
+            position += 258;
+        } else {
+            match raw[position] {
+                byte @ 0..=143 => writer.code(0x30 + byte as u32, 8),
+                byte => writer.code(0x190 + byte as u32 - 144, 9),
+            }
+            position += 1;
+        }
+    }
+    writer.code(0, 7); //End of block
+    let mut zlib = vec![0x78, 0x01];
+    zlib.extend_from_slice(&writer.bytes);
+    zlib.extend_from_slice(&adler32(raw).to_be_bytes());
+    zlib
+}
+
+//IHDR first and IEND last around the given chunks
+fn gray_png(middle: &[Vec<u8>]) -> Vec<u8> {
+    let mut chunks = vec![ihdr(1, 1, 8, 0)];
+    chunks.extend_from_slice(middle);
+    chunks.push(build_chunk(b"IDAT", &stored_zlib(&[0, 7])));
+    chunks.push(build_chunk(b"IEND", &[]));
+    build_png(&chunks)
+}
+
+fn parse(bytes: Vec<u8>) -> Result<Png<'static>, PngError> {
+    Png::with_options(bytes, DecodeOptions::default())
+}
+
+#[test]
+fn crc32_known_vectors() {
+    assert_eq!(crc32(b""), 0);
+    assert_eq!(crc32(b"123456789"), 0xCBF43926);
+    assert_eq!(crc32(b"IEND"), 0xAE426082);
+
+    let mut crc = Crc32::new();
+    crc.update(b"1234");
+    crc.update(b"56789");
+    assert_eq!(crc.finalize(), 0xCBF43926);
+}
+
+#[test]
+fn adler32_known_vectors() {
+    assert_eq!(adler32(b""), 1);
+    assert_eq!(adler32(b"Wikipedia"), 0x11E60398);
+
+    let mut adler = Adler32::new();
+    adler.update(b"Wiki");
+    adler.update(b"pedia");
+    assert_eq!(adler.finalize(), 0x11E60398);
+}
+
+//Both made by zlib.compress, the first with Z_FIXED so it's a single fixed Huffman block, the second picks dynamic codes on its own
+const FIXED_HUFFMAN_HELLO: [u8; 16] = [0x78, 0x01, 0xCB, 0x48, 0xCD, 0xC9, 0xC9, 0x57, 0xC8, 0x40, 0x27, 0x01, 0x68, 0x03, 0x08, 0xB1];
+const DYNAMIC_HUFFMAN_WIKIPEDIA: [u8; 102] = [
+    0x78, 0xDA, 0x2D, 0x8D, 0x51, 0x0E, 0x83, 0x30, 0x0C, 0x43, 0xAF, 0xE2, 0x03, 0x4C, 0x1C, 0x83, 0x2B, 0xF0, 0x1D, 0x5A,
+    0x23, 0x22, 0xBA, 0x64, 0x0A, 0x65, 0x13, 0xB7, 0x5F, 0x0B, 0xFC, 0x59, 0xF6, 0xB3, 0xDE, 0xA4, 0x9B, 0x7E, 0x98, 0x55,
+    0xA0, 0x3B, 0x04, 0x4B, 0x90, 0x70, 0x2B, 0x6A, 0x04, 0x2D, 0x9D, 0xA9, 0xF8, 0xB5, 0xBE, 0x90, 0x82, 0x52, 0x99, 0x21,
+    0x96, 0xD1, 0x9A, 0x1E, 0xE7, 0x13, 0x5F, 0x2F, 0x87, 0x55, 0x32, 0xDA, 0x39, 0xFC, 0x68, 0x5B, 0x5D, 0x89, 0x9F, 0x47,
+    0xB9, 0xC9, 0xD5, 0xF7, 0x87, 0xEC, 0xFD, 0xD4, 0x64, 0xEF, 0x4B, 0x36, 0x76, 0x56, 0xAA, 0xBA, 0x0D, 0x7F, 0x33, 0x61,
+    0x2F, 0xC5,
+];
+
+#[test]
+fn inflate_known_vectors() {
+    //Block type lives in bits 1-2 of the first byte after the header
+    assert_eq!((FIXED_HUFFMAN_HELLO[2] >> 1) & 3, 1);
+    assert_eq!((DYNAMIC_HUFFMAN_WIKIPEDIA[2] >> 1) & 3, 2);
+
+    assert_eq!(zlib_decompress(&FIXED_HUFFMAN_HELLO, true, None).unwrap(), b"hello hello hello hello");
+    let wikipedia: &[u8] = b"Wikipedia is a free online encyclopedia, created and edited by volunteers around the world and hosted by the Wikimedia Foundation.";
+    assert_eq!(zlib_decompress(&DYNAMIC_HUFFMAN_WIKIPEDIA, true, None).unwrap(), wikipedia);
+
+    //Same data with the last byte of the Adler-32 flipped, only an error when it's being checked
+    for vector in [&FIXED_HUFFMAN_HELLO[..], &DYNAMIC_HUFFMAN_WIKIPEDIA[..]] {
+        let mut bad_adler = vector.to_vec();
+        *bad_adler.last_mut().unwrap() ^= 0xFF;
+        let error = zlib_decompress(&bad_adler, true, None).unwrap_err();
+        assert!(matches!(&error, PngError::Decompression(message) if message.starts_with("Adler-32 mismatch")), "{error}");
+        assert_eq!(zlib_decompress(&bad_adler, false, None).unwrap(), zlib_decompress(vector, true, None).unwrap());
+    }
+}
+
+#[test]
+fn each_filter_type_reverses_its_row() {
+    //Two bytes per pixel so Sub, Average and Paeth have to reach back a whole pixel rather than one byte
+    let filtered = [
+        0, 10, 20, 30, 40,
+        1, 1, 2, 255, 4, //Sub, 255 + 1 wraps to 0
+        2, 1, 1, 1, 1, //Up
+        3, 5, 5, 5, 5, //Average, the sum is taken before halving so 6 + 7 doesn't wrap
+        4, 1, 1, 1, 1, //Paeth
+    ];
+    let rows = unfilter_rows(&filtered, 4, 5, 2, || Ok(())).unwrap();
+    assert_eq!(rows, [
+        10, 20, 30, 40,
+        1, 2, 0, 6,
+        2, 3, 1, 7,
+        6, 6, 8, 11,
+        7, 7, 9, 12,
+    ]);
+
+    let mut unknown_filter = filtered;
+    unknown_filter[15] = 5;
+    assert!(matches!(unfilter_rows(&unknown_filter, 4, 5, 2, || Ok(())), Err(PngError::MalformedChunk(message)) if message == "Unknown filter type 5"));
+}
+
+#[test]
+fn paeth_ties_go_to_left_then_up_then_upper_left() {
+    assert_eq!(paeth_predictor(30, 0, 10), 30); //pa == pc < pb
+    assert_eq!(paeth_predictor(0, 30, 10), 30); //pb == pc < pa
+    assert_eq!(paeth_predictor(20, 20, 20), 20);
+    assert_eq!(paeth_predictor(0, 6, 0), 6); //Nothing to the left on the first pixel
+    assert_eq!(paeth_predictor(10, 30, 20), 20);
+    assert_eq!(paeth_predictor(255, 255, 0), 255);
+}
+
+#[test]
+fn indexed_with_trns_expands_to_rgba() {
+    let bytes = build_png(&[
+        ihdr(3, 1, 8, 3),
+        build_chunk(b"PLTE", &[10, 20, 30, 40, 50, 60, 70, 80, 90]),
+        build_chunk(b"tRNS", &[128, 0]),
+        build_chunk(b"IDAT", &stored_zlib(&[0, 0, 1, 2])),
+        build_chunk(b"IEND", &[]),
+    ]);
+    let png = parse(bytes).unwrap();
+    //Entries past the end of tRNS are opaque
+    assert_eq!(png.to_rgba8().unwrap(), (3, 1, vec![10, 20, 30, 128, 40, 50, 60, 0, 70, 80, 90, 255]));
+}
+
+#[test]
+fn sixteen_color_sprite_with_transparent_index() {
+    //4 bit indices, entry 0 is the fully transparent background and tRNS stops after entry 2 so the rest are opaque
+    let palette: Vec<u8> = (0..16u8).flat_map(|index| [index * 16, 255 - index * 16, index]).collect();
+    let bytes = build_png(&[
+        ihdr(4, 2, 4, 3),
+        build_chunk(b"PLTE", &palette),
+        build_chunk(b"tRNS", &[0, 255, 100]),
+        build_chunk(b"IDAT", &stored_zlib(&[0, 0x01, 0x23, 0, 0x45, 0xEF])),
+        build_chunk(b"IEND", &[]),
+    ]);
+    let (width, height, rgba) = parse(bytes).unwrap().to_rgba8().unwrap();
+    assert_eq!((width, height), (4, 2));
+    let expected: Vec<u8> = [0u8, 1, 2, 3, 4, 5, 14, 15]
+        .iter()
+        .flat_map(|&index| {
+            let alpha = match index {
+                0 => 0,
+                2 => 100,
+                _ => 255,
+            };
+            [index * 16, 255 - index * 16, index, alpha]
+        })
+        .collect();
+    assert_eq!(rgba, expected);
+}
+
+#[test]
+fn ihdr_bytes_per_pixel_and_scanline_bytes() {
+    //(width, bit depth, color type, channels, bytes per pixel, scanline bytes)
+    let cases = [(3, 1, 0, 1, 1, 1), (9, 2, 3, 1, 1, 3), (5, 4, 0, 1, 1, 3), (7, 8, 2, 3, 3, 21), (3, 16, 4, 2, 4, 12), (5, 16, 6, 4, 8, 40)];
+    for (width, bit_depth, color_type, channels, bytes_per_pixel, scanline_bytes) in cases {
+        let png = parse(build_png(&[ihdr(width, 1, bit_depth, color_type), build_chunk(b"IEND", &[])])).unwrap();
+        let IHDR_chunk = png.find_ihdr().unwrap();
+        assert_eq!(IHDR_chunk.channels(), channels);
+        assert_eq!(IHDR_chunk.bytes_per_pixel(), bytes_per_pixel);
+        assert_eq!(IHDR_chunk.scanline_bytes().unwrap(), scanline_bytes);
+    }
+}
+
+#[test]
+fn lenient_mode_turns_broken_ancillary_chunks_into_warnings() {
+    let bytes = gray_png(&[build_chunk(b"gAMA", &0u32.to_be_bytes()), build_chunk(b"tEXt", b"Title\0kept")]);
+    let png = parse(bytes.clone()).unwrap();
+    assert_eq!(png.warnings().len(), 1);
+    assert_eq!(png.warnings()[0].chunk_type, "gAMA");
+    assert_eq!(png.warnings()[0].reason, "invalid gamma value 0");
+    assert!(matches!(png.chunks_of_type(*b"gAMA").next(), Some(Chunk::Unknown(..))));
+    assert_eq!(png.text_entries(), vec![("Title", "kept")]);
+
+    let strict = Png::with_options(bytes, DecodeOptions::default().strictness(Strictness::Strict));
+    assert!(matches!(strict, Err(PngError::MalformedChunk(..))));
+}
+
+#[test]
+fn lenient_mode_survives_a_wrong_fixed_chunk_length() {
+    let png = parse(gray_png(&[build_chunk(b"gAMA", &[0, 0, 0xB1, 0x8F, 0, 0, 0, 0]), build_chunk(b"tEXt", b"a\0b")])).unwrap();
+    assert_eq!(png.warnings().len(), 1);
+    assert_eq!(png.warnings()[0].chunk_type, "gAMA");
+    assert_eq!(png.warnings()[0].reason, "Invalid chunk length 8 for gAMA");
+    assert_eq!(png.text_entries(), vec![("a", "b")]);
+    assert_eq!(png.to_rgba8().unwrap().2, vec![7, 7, 7, 255]);
+
+    //bKGD's size depends on the color type, an RGB background on a gray image is the wrong length
+    let png = parse(gray_png(&[build_chunk(b"bKGD", &[0, 1, 0, 2, 0, 3])])).unwrap();
+    assert_eq!(png.warnings()[0].reason, "Invalid chunk length 6 for bKGD with color type Grayscale");
+
+    //Critical chunks can't be skipped
+    let mut bytes = gray_png(&[]);
+    let mut long_ihdr = ihdr(1, 1, 8, 0)[8..21].to_vec();
+    long_ihdr.push(0);
+    bytes.splice(8..33, build_chunk(b"IHDR", &long_ihdr));
+    assert!(matches!(parse(bytes), Err(PngError::MalformedChunk(message)) if message == "Invalid chunk length 14 for IHDR"));
+}
+
+#[test]
+fn to_bytes_round_trips_unmodified_files() {
+    let mut splt = b"palette\0\x08".to_vec();
+    splt.extend_from_slice(&[1, 2, 3, 4, 0, 9, 5, 6, 7, 8, 1, 0]);
+    let mut ztxt = b"Comment\0\0".to_vec();
+    ztxt.extend_from_slice(&stored_zlib(b"compressed"));
+    let bytes = gray_png(&[
+        build_chunk(b"gAMA", &45455u32.to_be_bytes()),
+        build_chunk(b"pHYs", &[0, 0, 0x0B, 0x13, 0, 0, 0x0B, 0x13, 1]),
+        build_chunk(b"sCAL", b"\x011.50\x002e-3"),
+        build_chunk(b"sPLT", &splt),
+        build_chunk(b"tIME", &[0x07, 0xE8, 2, 29, 23, 59, 60]),
+        build_chunk(b"tEXt", b"Author\0caf\xe9"),
+        build_chunk(b"zTXt", &ztxt),
+        build_chunk(b"iTXt", b"Title\0\0\0en\0Titel\0\xc3\xa9t\xc3\xa9"),
+        build_chunk(b"prIv", b"unknown ancillary"),
+    ]);
+    let png = parse(bytes.clone()).unwrap();
+    assert!(png.warnings().is_empty());
+    assert_eq!(png.to_bytes().unwrap(), bytes);
+}
+
+#[test]
+fn unterminated_strings_and_short_entries_stay_inside_the_chunk() {
+    let png = parse(gray_png(&[build_chunk(b"tEXt", b"no terminator"), build_chunk(b"iCCP", b"name")])).unwrap();
+    let reasons: Vec<&str> = png.warnings().iter().map(|warning| warning.reason.as_str()).collect();
+    assert_eq!(reasons, vec!["Null terminator missing before the end of the chunk"; 2]);
+
+    let png = parse(gray_png(&[build_chunk(b"sPLT", b"palette\0\x08\x01\x02\x03")])).unwrap();
+    assert_eq!(png.warnings()[0].reason, "sPLT entries don't fit evenly in 3 bytes");
+
+    //The decode carries on and the image is still there
+    assert_eq!(png.to_rgba8().unwrap().2, vec![7, 7, 7, 255]);
+}
+
+#[test]
+fn from_reader_parses_and_enforces_limits() {
+    let mut bytes = gray_png(&[build_chunk(b"tEXt", b"a\0b")]);
+    let length = bytes.len();
+    bytes.extend_from_slice(b"trailing junk");
+    let mut cursor = std::io::Cursor::new(bytes.clone());
+    let png = Png::from_reader(&mut cursor).unwrap();
+    assert_eq!(cursor.position() as usize, length);
+    assert_eq!(png.text_entries(), vec![("a", "b")]);
+    assert_eq!(png.to_rgba8().unwrap().2, vec![7, 7, 7, 255]);
+
+    let options = DecodeOptions::default().max_chunk_size(Some(2));
+    assert!(matches!(Png::from_reader_with_options(&bytes[..], options), Err(PngError::ChunkTooLarge { length: 13, .. })));
+    let options = DecodeOptions::default().max_chunks(Some(2));
+    assert!(matches!(Png::from_reader_with_options(&bytes[..], options), Err(PngError::LimitExceeded(..))));
+
+    //Only one chunk is held at a time but sizes and error offsets still refer to the whole file
+    assert_eq!(png.size_report().file_size as usize, length);
+    assert_eq!(png.size_report().discrepancy, 0);
+    let mut bad_crc = bytes.clone();
+    bad_crc[33 + 14] ^= 1;
+    assert!(matches!(Png::from_reader(&bad_crc[..]), Err(PngError::CrcMismatch { offset: 33, .. })));
+    //A source that ends early fails exactly like the same bytes in memory
+    for cut in [40, 44, 50] {
+        assert_eq!(Png::from_reader(&bytes[..cut]).unwrap_err().to_string(), parse(bytes[..cut].to_vec()).unwrap_err().to_string());
+    }
+    assert!(matches!(Png::from_reader(&bytes[..33]), Err(PngError::MissingIend)));
+}
+
+#[test]
+fn bad_signature_fails_even_in_lenient_mode() {
+    let mut bytes = gray_png(&[]);
+    bytes[0] = b'x';
+    let error = parse(bytes.clone()).unwrap_err();
+    assert!(matches!(error, PngError::InvalidSignature { found: [b'x', 80, 78, 71, 13, 10, 26, 10] }));
+
+    //Unless the real signature turns up within the skipped range
+    let mut padded = b"junk".to_vec();
+    padded.extend_from_slice(&gray_png(&[]));
+    assert!(parse(padded.clone()).is_err());
+    let png = Png::with_options(padded, DecodeOptions::default().skip_leading_garbage(Some(16))).unwrap();
+    assert_eq!(png.to_rgba8().unwrap().2, vec![7, 7, 7, 255]);
+}
+
+#[test]
+fn inflate_stops_at_the_output_limit() {
+    //One scanline too many for a 1x1 image
+    let bytes = build_png(&[ihdr(1, 1, 8, 0), build_chunk(b"IDAT", &stored_zlib(&[0, 7, 0, 7])), build_chunk(b"IEND", &[])]);
+    assert!(matches!(parse(bytes).unwrap().to_rgba8(), Err(PngError::LimitExceeded(..))));
+
+    let mut ztxt = b"Comment\0\0".to_vec();
+    ztxt.extend_from_slice(&stored_zlib(&[b'a'; 100]));
+    let png = parse(gray_png(&[build_chunk(b"zTXt", &ztxt)])).unwrap();
+    let Some(Chunk::zTXt(zTXt_chunk)) = png.chunks_of_type(*b"zTXt").next() else { panic!("zTXt not parsed") };
+    assert_eq!(zTXt_chunk.decompressed_text(Some(100)).unwrap().len(), 100);
+    assert!(matches!(zTXt_chunk.decompressed_text(Some(99)), Err(PngError::LimitExceeded(..))));
+}
+
+#[test]
+fn alpha_channel_needs_image_data() {
+    let png = parse(build_png(&[ihdr(1, 1, 8, 0), build_chunk(b"IEND", &[])])).unwrap();
+    assert!(matches!(png.alpha_channel(), Err(PngError::NoImageData)));
+    assert_eq!(parse(gray_png(&[])).unwrap().alpha_channel().unwrap(), (1, 1, vec![255]));
+}
+
+#[test]
+fn zero_dimensions_and_oversized_trns_are_rejected() {
+    for (width, height) in [(0, 1), (1, 0)] {
+        let bytes = build_png(&[ihdr(width, height, 8, 0), build_chunk(b"IEND", &[])]);
+        assert!(matches!(parse(bytes), Err(PngError::MalformedChunk(..))));
+    }
+
+    let indexed = |trns: &[u8]| build_png(&[
+        ihdr(1, 1, 8, 3),
+        build_chunk(b"PLTE", &[10, 20, 30, 40, 50, 60]),
+        build_chunk(b"tRNS", trns),
+        build_chunk(b"IDAT", &stored_zlib(&[0, 1])),
+        build_chunk(b"IEND", &[]),
+    ]);
+    assert!(parse(indexed(&[0, 128])).unwrap().warnings().is_empty());
+    let png = parse(indexed(&[0, 128, 255])).unwrap();
+    assert_eq!(png.warnings()[0].reason, "tRNS has 3 entries but the palette only has 2");
+    assert_eq!(png.to_rgba8().unwrap().2, vec![40, 50, 60, 255]);
+}
+
+#[test]
+fn scanlines_inflate_row_by_row_past_the_window() {
+    //Seven different rows over and over, 120 KB of raster so the inflate window gets trimmed while copies still reach back across it
+    let (width, height) = (300, 400);
+    let period: Vec<u8> = (0..7)
+        .flat_map(|y| std::iter::once(0).chain((0..width).map(move |x| (x * 3 + y * 13) as u8)))
+        .collect();
+    let raw: Vec<u8> = period.iter().cycle().take((width + 1) * height).copied().collect();
+    let zlib = repeating_zlib(&raw, period.len());
+    let with_idat = |zlib: &[u8]| build_png(&[ihdr(width as u32, height as u32, 8, 0), build_chunk(b"IDAT", zlib), build_chunk(b"IEND", &[])]);
+
+    let rows: Vec<Vec<u8>> = parse(with_idat(&zlib)).unwrap().scanlines().unwrap().collect::<Result<_, _>>().unwrap();
+    assert_eq!(rows.len(), height);
+    for (y, row) in rows.iter().enumerate() {
+        assert_eq!(row[..], raw[y * (width + 1) + 1..(y + 1) * (width + 1)]);
+    }
+
+    //The Adler-32 can only be checked once the whole stream is through, so a bad one fails the last row
+    let mut corrupt = zlib.clone();
+    *corrupt.last_mut().unwrap() ^= 1;
+    let rows: Vec<Result<Vec<u8>, PngError>> = parse(with_idat(&corrupt)).unwrap().scanlines().unwrap().collect();
+    assert!(rows[..height - 1].iter().all(Result::is_ok));
+    assert!(matches!(rows[height - 1], Err(PngError::Decompression(..))));
+}
+
+#[test]
+fn adam7_images_smaller_than_a_pass_block() {
+    //Gray pixels numbered 10 * y + x + 1, the raw data lists each non empty pass's rows (filter byte 0) in pass order
+    let cases: [(u32, u32, Vec<u8>); 3] = [
+        (1, 1, vec![0, 1]), //Only pass 1
+        //Passes 1, 4 and 6, nothing below the first row
+        (4, 1, vec![0, 1, 0, 3, 0, 2, 4]),
+        //Passes 2 and 3 start at x = 4 and y = 4 so they're empty, pass 6 has one pixel in each of its two rows
+        (3, 3, vec![0, 1, 0, 3, 0, 21, 23, 0, 2, 0, 22, 0, 11, 12, 13]),
+    ];
+    for (width, height, raw) in cases {
+        let mut header = width.to_be_bytes().to_vec();
+        header.extend_from_slice(&height.to_be_bytes());
+        header.extend_from_slice(&[8, 0, 0, 0, 1]);
+        let chunks = [build_chunk(b"IHDR", &header), build_chunk(b"IDAT", &stored_zlib(&raw)), build_chunk(b"IEND", &[])];
+        let (decoded_width, decoded_height, rgba) = parse(build_png(&chunks)).unwrap().to_rgba8().unwrap();
+        assert_eq!((decoded_width, decoded_height), (width, height));
+        let gray: Vec<u8> = rgba.chunks(4).map(|pixel| pixel[0]).collect();
+        let expected: Vec<u8> = (0..height).flat_map(|y| (0..width).map(move |x| (10 * y + x + 1) as u8)).collect();
+        assert_eq!(gray, expected, "{width}x{height}");
+    }
+}
+
+#[test]
+fn ihdr_must_come_first_and_nothing_may_follow_iend() {
+    let mut chunks = vec![build_chunk(b"bKGD", &[0, 0]), ihdr(1, 1, 8, 0)];
+    chunks.push(build_chunk(b"IDAT", &stored_zlib(&[0, 7])));
+    chunks.push(build_chunk(b"IEND", &[]));
+    assert!(matches!(parse(build_png(&chunks)), Err(PngError::IhdrNotFirst(chunk_type)) if chunk_type == "bKGD"));
+
+    let mut bytes = gray_png(&[]);
+    bytes.extend_from_slice(b"junk");
+    assert!(matches!(parse(bytes.clone()), Err(PngError::TrailingData(4))));
+    let png = Png::with_options(bytes, DecodeOptions::default().allow_trailing_data(true)).unwrap();
+    assert_eq!(png.warnings()[0].reason, "4 bytes after IEND");
+}
+
+#[test]
+fn skip_leading_garbage_finds_the_signature_after_a_bom() {
+    let mut bytes = vec![0xEF, 0xBB, 0xBF];
+    bytes.extend_from_slice(&gray_png(&[build_chunk(b"tEXt", b"a\0b")]));
+    assert!(matches!(parse(bytes.clone()), Err(PngError::InvalidSignature { found: [0xEF, 0xBB, 0xBF, 137, 80, 78, 71, 13] })));
+
+    let png = Png::with_options(bytes.clone(), DecodeOptions::default().skip_leading_garbage(Some(3))).unwrap();
+    assert_eq!(png.text_entries(), vec![("a", "b")]);
+    assert_eq!(png.to_rgba8().unwrap().2, vec![7, 7, 7, 255]);
+    //The signature has to start within the range
+    assert!(Png::with_options(bytes, DecodeOptions::default().skip_leading_garbage(Some(2))).is_err());
+}
+
+#[test]
+fn truncated_signature_reports_the_bytes_found() {
+    for constructor in [Png::from_bytes, parse] {
+        assert!(matches!(constructor(vec![137, 80, 78]), Err(PngError::InvalidSignature { found: [137, 80, 78, 0, 0, 0, 0, 0] })));
+        assert!(matches!(constructor(Vec::new()), Err(PngError::InvalidSignature { found: [0, 0, 0, 0, 0, 0, 0, 0] })));
+    }
+    assert!(matches!(Png::from_reader(&[137, 80][..]), Err(PngError::InvalidSignature { found: [137, 80, 0, 0, 0, 0, 0, 0] })));
+}
+
+#[test]
+fn borrowed_walker_checks_lengths_and_applies_the_options() {
+    let bytes = gray_png(&[build_chunk(b"tEXt", b"a\0b")]);
+    assert_eq!(parse_borrowed(&bytes).unwrap().len(), 4);
+
+    //With no size limit a length running past the end (all the way to u32::MAX) is an EOF, not an overflow or a panic
+    let unlimited = DecodeOptions::default().max_chunk_size(None);
+    let mut oversized = bytes.clone();
+    for length in [4u32, 1000, u32::MAX] {
+        oversized[33..37].copy_from_slice(&length.to_be_bytes());
+        let result = parse_borrowed_with(&oversized[..45], &unlimited);
+        assert!(matches!(result, Err(PngError::UnexpectedEof { at: 41, .. })), "length {length}");
+    }
+    //The default limit catches it before anything is sliced
+    assert!(matches!(parse_borrowed(&oversized), Err(PngError::ChunkTooLarge { length: u32::MAX, max: 67108864, .. })));
+
+    assert!(matches!(parse_borrowed_with(&bytes, &DecodeOptions::default().max_chunks(Some(3))), Err(PngError::LimitExceeded(_))));
+    assert_eq!(parse_borrowed_with(&bytes, &DecodeOptions::default().max_chunks(Some(4))).unwrap().len(), 4);
+
+    let mut bad_crc = bytes.clone();
+    bad_crc[44] ^= 1;
+    assert!(matches!(parse_borrowed(&bad_crc), Err(PngError::CrcMismatch { offset: 33, .. })));
+    assert_eq!(parse_borrowed_with(&bad_crc, &DecodeOptions::default().ignore_crc(true)).unwrap().len(), 4);
+}
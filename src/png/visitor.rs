@@ -0,0 +1,69 @@
+use crate::Png;
+use crate::png::{IHDRChunk, PLTEChunk, IDATChunk, IENDChunk, tIMEChunk, bKGDChunk, gAMAChunk, cHRMChunk, dSIGChunk, eXIfChunk, hISTChunk,
+    iCCPChunk, iTXtChunk, pHYsChunk, sBITChunk, sPLTChunk, sRGBChunk, sTERChunk, tEXtChunk, tRNSChunk, zTXtChunk, acTLChunk, fcTLChunk, fdATChunk, sCALChunk, UnknownChunk, Chunk};
+
+/*One method per chunk type, all empty by default so a processor (a metadata extractor say) only overrides the ones it cares about
+instead of matching every variant of Chunk itself. Png::accept calls them in file order*/
+pub trait ChunkVisitor {
+    fn visit_ihdr(&mut self, chunk: &IHDRChunk) {}
+    fn visit_plte(&mut self, chunk: &PLTEChunk) {}
+    fn visit_idat(&mut self, chunk: &IDATChunk) {}
+    fn visit_iend(&mut self, chunk: &IENDChunk) {}
+    fn visit_time(&mut self, chunk: &tIMEChunk) {}
+    fn visit_bkgd(&mut self, chunk: &bKGDChunk) {}
+    fn visit_gama(&mut self, chunk: &gAMAChunk) {}
+    fn visit_chrm(&mut self, chunk: &cHRMChunk) {}
+    fn visit_dsig(&mut self, chunk: &dSIGChunk) {}
+    fn visit_exif(&mut self, chunk: &eXIfChunk) {}
+    fn visit_hist(&mut self, chunk: &hISTChunk) {}
+    fn visit_iccp(&mut self, chunk: &iCCPChunk) {}
+    fn visit_itxt(&mut self, chunk: &iTXtChunk) {}
+    fn visit_phys(&mut self, chunk: &pHYsChunk) {}
+    fn visit_sbit(&mut self, chunk: &sBITChunk) {}
+    fn visit_splt(&mut self, chunk: &sPLTChunk) {}
+    fn visit_srgb(&mut self, chunk: &sRGBChunk) {}
+    fn visit_ster(&mut self, chunk: &sTERChunk) {}
+    fn visit_text(&mut self, chunk: &tEXtChunk) {}
+    fn visit_trns(&mut self, chunk: &tRNSChunk) {}
+    fn visit_ztxt(&mut self, chunk: &zTXtChunk) {}
+    fn visit_actl(&mut self, chunk: &acTLChunk) {}
+    fn visit_fctl(&mut self, chunk: &fcTLChunk) {}
+    fn visit_fdat(&mut self, chunk: &fdATChunk) {}
+    fn visit_scal(&mut self, chunk: &sCALChunk) {}
+    fn visit_unknown(&mut self, chunk: &UnknownChunk) {}
+}
+
+impl<'a> Png<'a> {
+    pub fn accept<V: ChunkVisitor>(&self, visitor: &mut V) {
+        for chunk in &self.chunk_list {
+            match chunk {
+                Chunk::IHDR(chunk) => visitor.visit_ihdr(chunk),
+                Chunk::PLTE(chunk) => visitor.visit_plte(chunk),
+                Chunk::IDAT(chunk) => visitor.visit_idat(chunk),
+                Chunk::IEND(chunk) => visitor.visit_iend(chunk),
+                Chunk::tIME(chunk) => visitor.visit_time(chunk),
+                Chunk::bKGD(chunk) => visitor.visit_bkgd(chunk),
+                Chunk::gAMA(chunk) => visitor.visit_gama(chunk),
+                Chunk::cHRM(chunk) => visitor.visit_chrm(chunk),
+                Chunk::dSIG(chunk) => visitor.visit_dsig(chunk),
+                Chunk::eXIf(chunk) => visitor.visit_exif(chunk),
+                Chunk::hIST(chunk) => visitor.visit_hist(chunk),
+                Chunk::iCCP(chunk) => visitor.visit_iccp(chunk),
+                Chunk::iTXt(chunk) => visitor.visit_itxt(chunk),
+                Chunk::pHYs(chunk) => visitor.visit_phys(chunk),
+                Chunk::sBIT(chunk) => visitor.visit_sbit(chunk),
+                Chunk::sPLT(chunk) => visitor.visit_splt(chunk),
+                Chunk::sRGB(chunk) => visitor.visit_srgb(chunk),
+                Chunk::sTER(chunk) => visitor.visit_ster(chunk),
+                Chunk::tEXt(chunk) => visitor.visit_text(chunk),
+                Chunk::tRNS(chunk) => visitor.visit_trns(chunk),
+                Chunk::zTXt(chunk) => visitor.visit_ztxt(chunk),
+                Chunk::acTL(chunk) => visitor.visit_actl(chunk),
+                Chunk::fcTL(chunk) => visitor.visit_fctl(chunk),
+                Chunk::fdAT(chunk) => visitor.visit_fdat(chunk),
+                Chunk::sCAL(chunk) => visitor.visit_scal(chunk),
+                Chunk::Unknown(chunk) => visitor.visit_unknown(chunk),
+            }
+        }
+    }
+}